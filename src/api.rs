@@ -0,0 +1,531 @@
+//Fetch API of BTCache (get, base64 and refresh functions) generated for both flavours from a single definition,
+//so the blocking and async functions always offer the same capabilities.
+//The sync fetch functions need the "blocking" feature; invalidate_cache only touches the disk and is always available.
+//The sync flavour uses the blocking HTTP client (download_file) and std::fs, the async flavour the async client (download_file_async)
+//and the non blocking file operations of fs_io.
+use std::{error::Error, path::Path};
+
+use base64::{Engine, engine::general_purpose};
+use bt_logger::{get_error, log_error, log_verbose, log_warning};
+
+#[cfg(feature = "async")]
+use crate::fs_io::nonblocking;
+use crate::{auth::Auth, cache::BTCache, fs_io::blocking, metadata::metadata_path, options::FetchOptions, vary::vary_record_path};
+
+macro_rules! fetch_api {
+    (
+        flavour: $flavour:literal,
+        fs: $fs:ident,
+        download: $download:ident,
+        invalidate: $invalidate:ident,
+        invalidate_file: $invalidate_file:ident,
+        record_hit: $record_hit:ident,
+        variant_entries: $variant_entries:ident,
+        await: [$($await:tt)*],
+        fetch_cfg: [$($fetch_cfg:meta)?],
+        $($async:ident)? {
+            get: $get:ident,
+            get_with_name: $get_with_name:ident,
+            get_with_name_token: $get_with_name_token:ident,
+            get_with_options: $get_with_options:ident,
+            get_from_mirrors: $get_from_mirrors:ident,
+            base64: $base64:ident,
+            base64_with_name: $base64_with_name:ident,
+            base64_with_name_token: $base64_with_name_token:ident,
+            base64_with_options: $base64_with_options:ident,
+            refresh: $refresh:ident,
+            refresh_with_name: $refresh_with_name:ident,
+            refresh_with_token: $refresh_with_token:ident,
+            refresh_with_options: $refresh_with_options:ident $(,)?
+        }
+    ) => {
+        impl BTCache {
+            #[doc = concat!($flavour, "Retrieve the local file path of the resource at url, downloading it on a cache miss.")]
+            ///
+            /// #Parameters:
+            ///     * url: A string slice containing the URL of the file to retrieve from cache. Also used as cache key
+            ///
+            /// #Returns:
+            ///     * Result<String, Box<dyn Error>>: Returns the full local file path as a string on success, or an error if
+            ///       the download or the file operations fail
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $get(&self, url: &str) -> Result<String, Box<dyn Error>> {
+                self.$get_with_options(url, url, &FetchOptions::default())$($await)*
+            }
+
+            #[doc = concat!($flavour, "Retrieve the local file path of the resource at url stored under file_name, downloading it on a cache miss.")]
+            ///
+            /// #Parameters:
+            ///     * url: A string slice containing the URL of the file to retrieve from cache.
+            ///     * file_name: desire file name or file id. Useful when file may associuted to multiple URLs
+            ///
+            /// #Returns:
+            ///     * Result<String, Box<dyn Error>>: Returns the full local file path as a string on success, or an error if
+            ///       the download or the file operations fail
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $get_with_name(&self, url: &str, file_name: &str) -> Result<String, Box<dyn Error>> {
+                self.$get_with_options(url, file_name, &FetchOptions::default())$($await)*
+            }
+
+            #[doc = concat!($flavour, "Retrieve the local file path of the resource at url stored under file_name, downloading it with the access token on a cache miss.")]
+            ///
+            /// #Parameters:
+            ///     * url: A string slice containing the URL of the file to retrieve from cache.
+            ///     * file_name: desire file name or file id. Useful when file may associuted to multiple URLs
+            ///     * token: Access token to be use to access the URL resource
+            ///
+            /// #Returns:
+            ///     * Result<String, Box<dyn Error>>: Returns the full local file path as a string on success, or an error if
+            ///       the download or the file operations fail
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $get_with_name_token(&self, url: &str, file_name: &str, token: Option<&str>) -> Result<String, Box<dyn Error>> {
+                let options = FetchOptions { auth: token.map(Auth::bearer), ..Default::default() };
+                self.$get_with_options(url, file_name, &options)$($await)*
+            }
+
+            #[doc = concat!($flavour, "Retrieve the local file path of the resource at url stored under file_name, downloading it with the fetch options on a cache miss.")]
+            ///
+            /// #Parameters:
+            ///     * url: A string slice containing the URL of the file to retrieve from cache.
+            ///     * file_name: desire file name or file id. Useful when file may associuted to multiple URLs
+            ///     * options: Fetch options such as the credentials, the request or a progress observer
+            ///
+            /// #Returns:
+            ///     * Result<String, Box<dyn Error>>: Returns the full local file path as a string on success, or an error if
+            ///       the download or the file operations fail
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $get_with_options(&self, url: &str, file_name: &str, options: &FetchOptions) -> Result<String, Box<dyn Error>> {
+                let file_name = &options.cache_key(file_name);
+                let mut int_file_path = self.entry_path(file_name, options);
+                let result = match $fs::exists(&int_file_path)$($await)* {
+                    Err(_) => {
+                        log_error!("get_local_file_path","Issue finding file '{:?}' trying downloading again",int_file_path);
+                        self.stats.record_miss();
+                        self.downloader().$download(url, &int_file_path, options, None)$($await)*
+                            .and_then(|d| self.store_variant(&mut int_file_path, file_name, options, d))
+                    },
+                    //File not found
+                    Ok(false) => {
+                        self.stats.record_miss();
+                        self.downloader().$download(url, &int_file_path, options, None)$($await)*
+                            .and_then(|d| self.store_variant(&mut int_file_path, file_name, options, d))
+                    },
+                    //Cached content that no longer matches the expected checksum (e.g. a new release) is downloaded again
+                    Ok(true) if !Self::matches_checksum(&int_file_path, options) => {
+                        log_verbose!(stringify!($get_with_options),"Cached '{}' does not match the expected checksum",file_name);
+                        self.stats.record_miss();
+                        self.downloader().$download(url, &int_file_path, options, None)$($await)*
+                            .and_then(|d| self.store_variant(&mut int_file_path, file_name, options, d))
+                    },
+                    Ok(true) => {
+                        self.$record_hit(&int_file_path, file_name, url)$($await)*;
+                        Ok(())
+                    },
+                };
+                self.complete_request(result, &int_file_path)
+            }
+
+            #[doc = concat!($flavour, "Retrieve the local file path of the resource stored under file_name, trying each mirror URL in turn on a cache miss.")]
+            ///The first mirror that serves the resource (and matches the checksum of the options, if any: SHA3-256 digest, or
+            ///"sha256:<hex>" / "sha512:<hex>", see FetchOptions::with_checksum) is cached, and its URL is
+            ///recorded in the entry metadata (CacheEntry::url). A cached entry is returned without contacting any mirror.
+            ///
+            /// #Parameters:
+            ///     * urls: Candidate URLs of the resource, in order of preference
+            ///     * file_name: file name or file id the resource is cached under
+            ///     * options: Fetch options such as the credentials or the expected checksum (FetchOptions::with_checksum)
+            ///
+            /// #Returns:
+            ///     * Result<String, Box<dyn Error>>: Returns the full local file path as a string on success, or an error listing
+            ///       the failure of every mirror
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $get_from_mirrors(&self, urls: &[&str], file_name: &str, options: &FetchOptions) -> Result<String, Box<dyn Error>> {
+                let mut failures = Vec::new();
+                for url in urls {
+                    match self.$get_with_options(url, file_name, options)$($await)* {
+                        Ok(path) => return Ok(path),
+                        Err(e) => {
+                            log_warning!(stringify!($get_from_mirrors),"Mirror '{}' failed for '{}': {}",url,file_name,e);
+                            failures.push(format!("{}: {}", url, e));
+                        },
+                    }
+                }
+                if failures.is_empty() {
+                    return Err(get_error!(stringify!($get_from_mirrors),"No mirror URL given for '{}'",file_name).into())
+                }
+                Err(get_error!(stringify!($get_from_mirrors),"No mirror could serve '{}': {}",file_name,failures.join("; ")).into())
+            }
+
+            #[doc = concat!($flavour, "Encodes the bytes of the cached file of url using standard base64 encoding")]
+            ///
+            ///#Parameters
+            ///     * url: A string slice (&str) containing the URL of the file to retrieve
+            ///
+            ///#Returns
+            ///    Result<String, Box<dyn Error>>: Returns a String containing the base64-encoded file data on success, or an error if
+            ///    the file cannot be retrieved or read from the local cache
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $base64(&self, url: &str) -> Result<String, Box<dyn Error>> {
+                self.$base64_with_options(url, url, &FetchOptions::default())$($await)*
+            }
+
+            #[doc = concat!($flavour, "Encodes the bytes of the cached file stored under file_name using standard base64 encoding")]
+            ///
+            ///#Parameters
+            ///     * url: A string slice (&str) containing the URL of the file to retrieve
+            ///     * file_name: desire file name or file id. Useful when file may associuted to multiple URLs
+            ///
+            ///#Returns
+            ///    Result<String, Box<dyn Error>>: Returns a String containing the base64-encoded file data on success, or an error if
+            ///    the file cannot be retrieved or read from the local cache
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $base64_with_name(&self, url: &str, file_name: &str) -> Result<String, Box<dyn Error>> {
+                self.$base64_with_options(url, file_name, &FetchOptions::default())$($await)*
+            }
+
+            #[doc = concat!($flavour, "Encodes the bytes of the cached file stored under file_name, downloaded with the access token, using standard base64 encoding")]
+            ///
+            ///#Parameters
+            ///     * url: A string slice (&str) containing the URL of the file to retrieve
+            ///     * file_name: desire file name or file id. Useful when file may associuted to multiple URLs
+            ///     * token: Access token to access the URL resource
+            ///
+            ///#Returns
+            ///    Result<String, Box<dyn Error>>: Returns a String containing the base64-encoded file data on success, or an error if
+            ///    the file cannot be retrieved or read from the local cache
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $base64_with_name_token(&self, url: &str, file_name: &str, token: Option<&str>) -> Result<String, Box<dyn Error>> {
+                let options = FetchOptions { auth: token.map(Auth::bearer), ..Default::default() };
+                self.$base64_with_options(url, file_name, &options)$($await)*
+            }
+
+            #[doc = concat!($flavour, "Encodes the bytes of the cached file stored under file_name, downloaded with the fetch options, using standard base64 encoding")]
+            ///
+            ///#Parameters
+            ///     * url: A string slice (&str) containing the URL of the file to retrieve
+            ///     * file_name: desire file name or file id. Useful when file may associuted to multiple URLs
+            ///     * options: Fetch options such as the credentials, the request or a progress observer
+            ///
+            ///#Returns
+            ///    Result<String, Box<dyn Error>>: Returns a String containing the base64-encoded file data on success, or an error if
+            ///    the file cannot be retrieved or read from the local cache
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $base64_with_options(&self, url: &str, file_name: &str, options: &FetchOptions) -> Result<String, Box<dyn Error>> {
+                let full_file_path = self.$get_with_options(url, file_name, options)$($await)*?;
+                log_verbose!(stringify!($base64_with_options),"Getting '{}' = '{}'",url,full_file_path);
+                let file_data_bytes = $fs::read(full_file_path)$($await)*?;
+                Ok(general_purpose::STANDARD.encode(file_data_bytes))
+            }
+
+            #[doc = concat!($flavour, "Remove the cached file of a URL or name (every variant when the entry varies on request headers).")]
+            ///Subsequent requests for the URL fetch fresh data rather than using the cached content.
+            ///
+            /// #Parameters
+            /// * url_name_id: &str, A string slice representing the URL or Name ID of the cached resource to be invalidated
+            ///
+            /// #Returns
+            /// Result<(), Box<dyn Error>>
+            ///     * Success: Ok(()) - Indicates that the cache file was successfully removed
+            ///     * Error: Err(Box<dyn Error>) - Contains a boxed error object describing what went wrong during the cache invalidation process
+            pub $($async)? fn $invalidate(&self, url_name_id: &str) -> Result<(), Box<dyn Error>> {
+                //Entries cached per variant (Vary header): every variant is removed
+                let variants = self.$variant_entries(url_name_id)$($await)*;
+                if !variants.is_empty() {
+                    for variant in variants {
+                        $fs::remove_file(&variant)$($await)*?;
+                        let _ = $fs::remove_file(metadata_path(&variant))$($await)*;
+                        self.stats.record_eviction();
+                    }
+                    let _ = $fs::remove_file(vary_record_path(&self.get_file(url_name_id)))$($await)*;
+                    return Ok(())
+                }
+
+                self.$invalidate_file(&self.get_file(url_name_id))$($await)*
+            }
+
+            //Remove one cache file and its metadata
+            pub(crate) $($async)? fn $invalidate_file(&self, file: &Path) -> Result<(), Box<dyn Error>> {
+                match $fs::exists(file)$($await)* {
+                    Ok(true) => {
+                        $fs::remove_file(file)$($await)*?;
+                        let _ = $fs::remove_file(metadata_path(file))$($await)*;
+                        self.stats.record_eviction();
+                    },
+                    Ok(false) => return Err(get_error!("invalidate_cache","File not found").into()),
+                    Err(e) => return Err(get_error!("invalidate_cache","File check Error: {}",e).into()),
+                }
+                Ok(())
+            }
+
+            #[doc = concat!($flavour, "Refresh or revalidate the cached resource of url and return the local file path where the refreshed content is stored.")]
+            ///When the server provided an ETag or Last-Modified header, the entry is revalidated with a conditional request
+            ///and the cached file is kept if the server answers 304 Not Modified. Otherwise the entry is invalidated and downloaded again.
+            ///
+            /// #Parameters
+            /// * url: &str, A string slice representing the URL of the cached resource to refresh. Also used as cache key
+            ///
+            /// #Returns
+            /// Result<String, Box<dyn Error>>:
+            ///     * Success: Ok(String) - Returns the local file path where the refreshed cache content is stored
+            ///     * Error: Err(Box<dyn Error>) - Contains a boxed error object describing what went wrong during the cache refresh process
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $refresh(&self, url: &str) -> Result<String, Box<dyn Error>> {
+                self.$refresh_with_options(url, url, &FetchOptions::default())$($await)*
+            }
+
+            #[doc = concat!($flavour, "Refresh or revalidate the cached resource stored under name, downloading it with the access token.")]
+            ///
+            /// #Parameters
+            /// * url: &str, A string slice representing the URL to refresh
+            /// * name: name of the file to store. This parameter is used to determine which cached file should be refreshed
+            /// * token: access token to access the URL resource
+            ///
+            /// #Returns
+            /// Result<String, Box<dyn Error>>:
+            ///     * Success: Ok(String) - Returns the local file path where the refreshed cache content is stored
+            ///     * Error: Err(Box<dyn Error>) - Contains a boxed error object describing what went wrong during the cache refresh process
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $refresh_with_name(&self, url: &str, name: &str, token: Option<&str>) -> Result<String, Box<dyn Error>> {
+                let options = FetchOptions { auth: token.map(Auth::bearer), ..Default::default() };
+                self.$refresh_with_options(url, name, &options)$($await)*
+            }
+
+            #[doc = concat!($flavour, "Refresh or revalidate the cached resource of url, downloading it with the access token.")]
+            ///
+            /// #Parameters
+            /// * url: &str, A string slice representing the URL to refresh. Also used as cache key
+            /// * token: access token to access the URL resource
+            ///
+            /// #Returns
+            /// Result<String, Box<dyn Error>>:
+            ///     * Success: Ok(String) - Returns the local file path where the refreshed cache content is stored
+            ///     * Error: Err(Box<dyn Error>) - Contains a boxed error object describing what went wrong during the cache refresh process
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $refresh_with_token(&self, url: &str, token: Option<&str>) -> Result<String, Box<dyn Error>> {
+                self.$refresh_with_name(url, url, token)$($await)*
+            }
+
+            #[doc = concat!($flavour, "Refresh or revalidate the cached resource stored under name, downloading it with the fetch options.")]
+            ///
+            /// #Parameters
+            /// * url: &str, A string slice representing the URL to refresh
+            /// * name: name of the file to store. This parameter is used to determine which cached file should be refreshed
+            /// * options: Fetch options such as the credentials, the request or a progress observer
+            ///
+            /// #Returns
+            /// Result<String, Box<dyn Error>>:
+            ///     * Success: Ok(String) - Returns the local file path where the refreshed cache content is stored
+            ///     * Error: Err(Box<dyn Error>) - Contains a boxed error object describing what went wrong during the cache refresh process
+            $(#[$fetch_cfg])?
+            pub $($async)? fn $refresh_with_options(&self, url: &str, name: &str, options: &FetchOptions) -> Result<String, Box<dyn Error>> {
+                let key = options.cache_key(name);
+                let mut int_file_path = self.entry_path(&key, options);
+                let Some(validators) = Self::get_validators(&int_file_path) else {
+                    //Only the requested variant is dropped when the entry varies on request headers
+                    if int_file_path == self.get_file(&key) { self.$invalidate(&key)$($await)*? } else { self.$invalidate_file(&int_file_path)$($await)*? }
+                    return self.$get_with_options(url, name, options)$($await)*
+                };
+
+                let result = self.downloader().$download(url, &int_file_path, options, Some(&validators))$($await)*
+                    .and_then(|d| self.store_revalidation(&mut int_file_path, &key, options, d));
+                self.complete_request(result, &int_file_path)
+            }
+        }
+    };
+}
+
+fetch_api! {
+    flavour: "",
+    fs: blocking,
+    download: download_file,
+    invalidate: invalidate_cache,
+    invalidate_file: invalidate_file,
+    record_hit: record_hit,
+    variant_entries: variant_entries,
+    await: [],
+    fetch_cfg: [cfg(feature = "blocking")],
+    {
+        get: get_local_file_path,
+        get_with_name: get_local_file_path_with_name,
+        get_with_name_token: get_local_file_path_with_name_token,
+        get_with_options: get_local_file_path_with_options,
+        get_from_mirrors: get_local_file_path_from_mirrors,
+        base64: get_file_data_base64,
+        base64_with_name: get_file_data_base64_with_name,
+        base64_with_name_token: get_file_data_base64_with_name_token,
+        base64_with_options: get_file_data_base64_with_options,
+        refresh: refresh_cache,
+        refresh_with_name: refresh_cache_with_name,
+        refresh_with_token: refresh_cache_with_token,
+        refresh_with_options: refresh_cache_with_options,
+    }
+}
+
+#[cfg(feature = "async")]
+fetch_api! {
+    flavour: "ASYNC ",
+    fs: nonblocking,
+    download: download_file_async,
+    invalidate: invalidate_cache_async,
+    invalidate_file: invalidate_file_async,
+    record_hit: record_hit_async,
+    variant_entries: variant_entries_async,
+    await: [.await],
+    fetch_cfg: [],
+    async {
+        get: get_local_file_path_async,
+        get_with_name: get_local_file_path_with_name_async,
+        get_with_name_token: get_local_file_path_with_name_token_async,
+        get_with_options: get_local_file_path_with_options_async,
+        get_from_mirrors: get_local_file_path_from_mirrors_async,
+        base64: get_file_data_base64_async,
+        base64_with_name: get_file_data_base64_with_name_async,
+        base64_with_name_token: get_file_data_base64_with_name_token_async,
+        base64_with_options: get_file_data_base64_with_options_async,
+        refresh: refresh_cache_async,
+        refresh_with_name: refresh_cache_with_name_async,
+        refresh_with_token: refresh_cache_with_token_async,
+        refresh_with_options: refresh_cache_with_options_async,
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod api_tests {
+    #[cfg(feature = "blocking")]
+    use std::fs;
+
+    #[cfg(feature = "blocking")]
+    use sha2::{Sha256, Sha512};
+    use sha3::{Digest, Sha3_256};
+
+    use super::*;
+    use crate::{metadata::hex_digest, test_server::{TestResponse, TestServer}, test_support::TempCache};
+
+    fn mirror_server() -> TestServer {
+        TestServer::start(|req| match req.path() {
+            "/down" => TestResponse::status(503, b""),
+            "/tampered" => TestResponse::ok(b"tampered"),
+            _ => TestResponse::ok(b"artifact"),
+        })
+    }
+
+    fn checksum(data: &[u8]) -> String {
+        hex_digest(Sha3_256::new_with_prefix(data))
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_sync_name_and_token_functions() {
+        let server = TestServer::start(|req| TestResponse::ok(req.header("Authorization").unwrap_or("anonymous").as_bytes()));
+        let local_cache = TempCache::new("api");
+        let url = server.url("/api_sync");
+
+        let p = local_cache.get_local_file_path_with_name_token(&url, "report", Some("t1")).unwrap();
+        assert_eq!(fs::read(&p).unwrap(), b"Bearer t1");
+        assert_eq!(local_cache.get_local_file_path_with_name(&url, "report").unwrap(), p);
+        assert_eq!(local_cache.get_file_data_base64_with_name(&url, "report").unwrap(), general_purpose::STANDARD.encode("Bearer t1"));
+        assert_eq!(local_cache.get_file_data_base64_with_name_token(&url, "report", Some("t2")).unwrap(), general_purpose::STANDARD.encode("Bearer t1"));
+
+        let refreshed = local_cache.refresh_cache_with_name(&url, "report", Some("t2")).unwrap();
+        assert_eq!(fs::read(refreshed).unwrap(), b"Bearer t2");
+        assert_eq!(fs::read(local_cache.get_local_file_path(&url).unwrap()).unwrap(), b"anonymous");
+        let refreshed = local_cache.refresh_cache_with_token(&url, Some("t3")).unwrap();
+        assert_eq!(fs::read(refreshed).unwrap(), b"Bearer t3");
+        assert_eq!(server.request_count(), 4);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_mirrors_with_checksum() {
+        let server = mirror_server();
+        let local_cache = TempCache::new("api_mirrors");
+        let mirrors = [server.url("/down"), server.url("/tampered"), server.url("/good")];
+        let mirrors: Vec<&str> = mirrors.iter().map(String::as_str).collect();
+        let options = FetchOptions::new().with_checksum(&checksum(b"artifact"));
+
+        let p = local_cache.get_local_file_path_from_mirrors(&mirrors, "artifact", &options).unwrap();
+        assert_eq!(fs::read(&p).unwrap(), b"artifact");
+        assert_eq!(local_cache.entry_info("artifact").unwrap().unwrap().url.as_deref(), Some(mirrors[2]));
+        assert_eq!(local_cache.get_local_file_path_from_mirrors(&mirrors, "artifact", &options).unwrap(), p);
+        assert_eq!(server.request_count(), 3);
+        assert_eq!(local_cache.file_count(), 2);
+
+        //A cached entry that does not match the expected checksum is fetched again
+        let options = FetchOptions::new().with_checksum(&checksum(b"tampered"));
+        local_cache.get_local_file_path_from_mirrors(&mirrors, "artifact", &options).unwrap();
+        assert_eq!(fs::read(&p).unwrap(), b"tampered");
+        assert_eq!(local_cache.entry_info("artifact").unwrap().unwrap().url.as_deref(), Some(mirrors[1]));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_mirrors_with_tagged_checksum() {
+        let server = mirror_server();
+        let local_cache = TempCache::new("api_mirrors_tagged");
+        let mirrors = [server.url("/tampered"), server.url("/good")];
+        let mirrors: Vec<&str> = mirrors.iter().map(String::as_str).collect();
+        let sha256: String = Sha256::digest(b"artifact").iter().map(|b| format!("{:02x}", b)).collect();
+        let options = FetchOptions::new().with_checksum(&format!("sha256:{}", sha256));
+
+        let p = local_cache.get_local_file_path_from_mirrors(&mirrors, "artifact", &options).unwrap();
+        assert_eq!(fs::read(&p).unwrap(), b"artifact");
+        assert_eq!(local_cache.get_local_file_path_from_mirrors(&mirrors, "artifact", &options).unwrap(), p);
+        assert_eq!(server.request_count(), 2);
+
+        //The SHA-512 checksum was never verified for the cached entry: it is fetched again
+        let sha512: String = Sha512::digest(b"artifact").iter().map(|b| format!("{:02x}", b)).collect();
+        let options = FetchOptions::new().with_checksum(&format!("SHA512:{}", sha512));
+        local_cache.get_local_file_path_from_mirrors(&mirrors, "artifact", &options).unwrap();
+        assert_eq!(server.request_count(), 4);
+        let options = FetchOptions::new().with_checksum(&format!("sha512:{}", sha256));
+        assert!(local_cache.get_local_file_path_from_mirrors(&mirrors, "other", &options).is_err());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_mirrors_all_failing_async() {
+        let server = mirror_server();
+        let local_cache = TempCache::new("api_mirrors_async");
+        let mirrors = [server.url("/down"), server.url("/tampered")];
+        let mirrors: Vec<&str> = mirrors.iter().map(String::as_str).collect();
+        let options = FetchOptions::new().with_checksum(&checksum(b"artifact"));
+
+        let r = local_cache.get_local_file_path_from_mirrors_async(&mirrors, "artifact", &options).await;
+
+        assert!(r.is_err());
+        assert_eq!(server.request_count(), 2);
+        assert!(local_cache.get_local_file_path_from_mirrors_async(&[], "artifact", &options).await.is_err());
+        assert_eq!(local_cache.file_count(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_base64_with_options() {
+        let server = TestServer::start(|req| TestResponse::ok(req.header("Accept").unwrap_or("-").as_bytes()));
+        let local_cache = TempCache::new("api_async");
+        let options = FetchOptions::new().with_header("Accept", "text/csv");
+
+        let data = local_cache.get_file_data_base64_with_options_async(&server.url("/api_async"), "csv", &options).await.unwrap();
+
+        assert_eq!(data, general_purpose::STANDARD.encode("text/csv"));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_invalidate_async() {
+        let server = TestServer::start(|_| TestResponse::ok(b"to remove"));
+        let local_cache = TempCache::new("api_invalidate");
+        let url = server.url("/api_invalidate");
+
+        let p = local_cache.get_local_file_path_async(&url).await.unwrap();
+        assert!(metadata_path(Path::new(&p)).exists());
+        local_cache.invalidate_cache_async(&url).await.unwrap();
+
+        assert!(!Path::new(&p).exists());
+        assert!(!metadata_path(Path::new(&p)).exists());
+        assert!(local_cache.invalidate_cache_async(&url).await.is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use std::{collections::HashMap, error::Error, fmt, sync::{Arc, RwLock}};
+
+use base64::{Engine, engine::general_purpose};
+use reqwest::{Url, header::AUTHORIZATION};
+use sha3::{Digest, Sha3_256};
+
+use crate::metadata::hex_digest;
+
+///Callback returning the credentials to use for one request (e.g. a token read from a vault or renewed when expired)
+pub type AuthCallback = Arc<dyn Fn() -> Result<Auth, Box<dyn Error>> + Send + Sync>;
+
+///Provider of refreshed credentials, called by BTCache when a download is rejected with 401 Unauthorized or 403 Forbidden.
+///Receives the URL and the response status, and returns the credentials used to retry the request once.
+pub type CredentialProvider = Arc<dyn Fn(&str, u16) -> Result<Auth, Box<dyn Error>> + Send + Sync>;
+
+///Credentials sent when downloading a URL resource. Set them with FetchOptions::with_auth,
+///they are applied identically by the sync and async functions.
+#[derive(Clone)]
+pub enum Auth {
+    ///Authorization: Bearer <token>
+    Bearer(String),
+    ///HTTP Basic authentication (Authorization: Basic base64(username:password))
+    Basic { username: String, password: Option<String> },
+    ///Custom header, e.g. X-API-Key: <key>
+    Header { name: String, value: String },
+    ///Query parameter added to the URL, e.g. ?api_key=<key>. The parameter is not part of the cache key
+    Query { name: String, value: String },
+    ///Credentials supplied by a callback, called before each request
+    Callback(AuthCallback),
+}
+
+impl fmt::Debug for Auth {
+    //Secrets are never printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Bearer(_) => f.write_str("Auth::Bearer(***)"),
+            Auth::Basic { username, .. } => write!(f, "Auth::Basic({}:***)", username),
+            Auth::Header { name, .. } => write!(f, "Auth::Header({}: ***)", name),
+            Auth::Query { name, .. } => write!(f, "Auth::Query({}=***)", name),
+            Auth::Callback(_) => f.write_str("Auth::Callback"),
+        }
+    }
+}
+
+//Credentials ready to be added to a request
+pub(crate) enum ResolvedAuth {
+    Header(String, String),
+    Query(String, String),
+}
+
+impl Auth {
+    ///Bearer token
+    pub fn bearer(token: &str) -> Self {
+        Auth::Bearer(token.to_owned())
+    }
+
+    ///HTTP Basic authentication
+    pub fn basic(username: &str, password: Option<&str>) -> Self {
+        Auth::Basic { username: username.to_owned(), password: password.map(str::to_owned) }
+    }
+
+    ///Custom header (e.g. X-API-Key)
+    pub fn header(name: &str, value: &str) -> Self {
+        Auth::Header { name: name.to_owned(), value: value.to_owned() }
+    }
+
+    ///Query parameter (e.g. api_key)
+    pub fn query(name: &str, value: &str) -> Self {
+        Auth::Query { name: name.to_owned(), value: value.to_owned() }
+    }
+
+    ///Credentials supplied by a callback before each request
+    pub fn callback<F>(callback: F) -> Self
+    where
+        F: Fn() -> Result<Auth, Box<dyn Error>> + Send + Sync + 'static,
+    {
+        Auth::Callback(Arc::new(callback))
+    }
+
+    //Credentials to send, calling the callback if needed (a callback returning another callback is not followed)
+    pub(crate) fn resolve(&self) -> Result<ResolvedAuth, Box<dyn Error>> {
+        match self {
+            Auth::Callback(cb) => match cb()? {
+                Auth::Callback(_) => Err("Authentication callback returned another callback".into()),
+                auth => auth.resolve(),
+            },
+            other => other.resolve_static().ok_or_else(|| "Missing credentials".into()),
+        }
+    }
+
+    //Credentials known without calling a callback
+    fn resolve_static(&self) -> Option<ResolvedAuth> {
+        match self {
+            Auth::Bearer(t) => Some(ResolvedAuth::Header(AUTHORIZATION.to_string(), format!("Bearer {}", t))),
+            Auth::Basic { username, password } => {
+                let credentials = format!("{}:{}", username, password.as_deref().unwrap_or(""));
+                Some(ResolvedAuth::Header(AUTHORIZATION.to_string(), format!("Basic {}", general_purpose::STANDARD.encode(credentials))))
+            }
+            Auth::Header { name, value } => Some(ResolvedAuth::Header(name.to_ascii_lowercase(), value.clone())),
+            Auth::Query { name, value } => Some(ResolvedAuth::Query(name.clone(), value.clone())),
+            Auth::Callback(_) => None,
+        }
+    }
+
+    //Header sent by static credentials, used to select the cached variant (Vary)
+    pub(crate) fn static_header(&self, name: &str) -> Option<String> {
+        match self.resolve_static() {
+            Some(ResolvedAuth::Header(n, v)) if n.eq_ignore_ascii_case(name) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+//Credentials returned by the credential provider, kept per origin (scheme, host and port) and rejected credentials, so the
+//later requests sending the same credentials to the same server start with the refreshed ones instead of being rejected and
+//refreshed again. Requests with other credentials are never given them. Shared by a BTCache and its downloads
+#[derive(Clone, Default)]
+pub(crate) struct RefreshedCredentials(Arc<RwLock<HashMap<(String, String), Auth>>>);
+
+impl RefreshedCredentials {
+    //Credentials refreshed for the given credentials of the fetch (None: no credentials)
+    pub(crate) fn get(&self, url: &str, original: Option<&Auth>) -> Option<Auth> {
+        let key = refresh_key(url, original)?;
+        self.0.read().ok()?.get(&key).cloned()
+    }
+
+    pub(crate) fn set(&self, url: &str, original: Option<&Auth>, auth: Auth) {
+        if let (Some(key), Ok(mut refreshed)) = (refresh_key(url, original), self.0.write()) {
+            refreshed.insert(key, auth);
+        }
+    }
+}
+
+//Origin of a URL and digest of the credentials sent to it (the secrets are not kept as keys).
+//None for URLs without a host (e.g. data: URIs) and credentials supplied by a callback, which resolves them on each request
+fn refresh_key(url: &str, original: Option<&Auth>) -> Option<(String, String)> {
+    let origin = Url::parse(url).ok()?.origin();
+    if !origin.is_tuple() {
+        return None
+    }
+    let credentials = match original.map(Auth::resolve_static) {
+        None => "-".to_owned(),
+        Some(Some(ResolvedAuth::Header(name, value))) => format!("header:{}:{}", name, value),
+        Some(Some(ResolvedAuth::Query(name, value))) => format!("query:{}={}", name, value),
+        Some(None) => return None,
+    };
+    Some((origin.ascii_serialization(), hex_digest(Sha3_256::new_with_prefix(credentials))))
+}
+
+impl ResolvedAuth {
+    //Add the query parameter to the URL (header credentials are added to the request builder)
+    pub(crate) fn apply_to_url(&self, url: &mut Url) {
+        if let ResolvedAuth::Query(name, value) = self {
+            url.query_pairs_mut().append_pair(name, value);
+        }
+    }
+
+    pub(crate) fn header(&self) -> Option<(&str, &str)> {
+        match self {
+            ResolvedAuth::Header(name, value) => Some((name, value)),
+            ResolvedAuth::Query(..) => None,
+        }
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod auth_tests {
+    use std::{
+        fs,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::{
+        cache::BTCache,
+        options::FetchOptions,
+        test_server::{TestResponse, TestServer},
+        test_support::TempCache,
+    };
+
+    fn echo_server() -> TestServer {
+        TestServer::start(|req| {
+            let body = format!(
+                "auth={};key={};target={}",
+                req.header("Authorization").unwrap_or("-"),
+                req.header("X-API-Key").unwrap_or("-"),
+                req.target
+            );
+            TestResponse::ok(body.as_bytes())
+        })
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_auth_kinds_sync() {
+        let server = echo_server();
+        let local_cache = TempCache::new("auth_sync");
+        let fetch = |path: &str, auth: Auth| {
+            let url = server.url(path);
+            let p = local_cache.get_local_file_path_with_options(&url, &url, &FetchOptions::new().with_auth(auth)).unwrap();
+            String::from_utf8(fs::read(p).unwrap()).unwrap()
+        };
+
+        assert!(fetch("/bearer", Auth::bearer("t1")).starts_with("auth=Bearer t1;"));
+        assert!(fetch("/basic", Auth::basic("user", Some("pass"))).starts_with("auth=Basic dXNlcjpwYXNz;"));
+        assert!(fetch("/header", Auth::header("X-API-Key", "k1")).contains(";key=k1;"));
+        assert!(fetch("/query?a=1", Auth::query("api_key", "k2")).ends_with("target=/query?a=1&api_key=k2"));
+
+        let url = server.url("/query?a=1");
+        assert_eq!(local_cache.entry_info(&url).unwrap().unwrap().url.as_deref(), Some(url.as_str()));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_auth_callback_async() {
+        let server = echo_server();
+        let local_cache = TempCache::new("auth_callback");
+        let calls = Arc::new(AtomicUsize::new(0));
+        let th_calls = calls.clone();
+        let auth = Auth::callback(move || Ok(Auth::bearer(&format!("fresh-{}", th_calls.fetch_add(1, Ordering::SeqCst)))));
+        let options = FetchOptions::new().with_auth(auth);
+
+        let p1 = local_cache.get_local_file_path_with_options_async(&server.url("/cb1"), "cb1", &options).await.unwrap();
+        let p2 = local_cache.get_local_file_path_with_options_async(&server.url("/cb2"), "cb2", &options).await.unwrap();
+
+        assert!(fs::read_to_string(p1).unwrap().starts_with("auth=Bearer fresh-0;"));
+        assert!(fs::read_to_string(p2).unwrap().starts_with("auth=Bearer fresh-1;"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    fn expiring_token_server() -> TestServer {
+        TestServer::start(|req| match req.header("Authorization") {
+            Some("Bearer renewed") => TestResponse::ok(b"protected"),
+            Some(_) if req.path() == "/forbidden" => TestResponse::status(403, b"forbidden"),
+            _ => TestResponse::status(401, b"unauthorized"),
+        })
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_credential_provider_retries_once_sync() {
+        let server = expiring_token_server();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let th_calls = calls.clone();
+        let local_cache = TempCache::with("auth_refresh", |c| {
+            c.with_credential_provider(move |_, status| {
+                assert_eq!(status, 401);
+                th_calls.fetch_add(1, Ordering::SeqCst);
+                Ok(Auth::bearer("renewed"))
+            })
+        });
+        let url = server.url("/protected");
+
+        let p = local_cache.get_local_file_path_with_options(&url, &url, &FetchOptions::new().with_token("expired")).unwrap();
+
+        assert_eq!(fs::read(p).unwrap(), b"protected");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(server.request_count(), 2);
+
+        //The refreshed credentials are kept for the same server: no rejected request, no provider call
+        let other = server.url("/other");
+        local_cache.get_local_file_path_with_options(&other, &other, &FetchOptions::new().with_token("expired")).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(server.request_count(), 3);
+
+        //The refreshed credentials are rejected as well: no second retry
+        let rejecting = BTCache::with_dir(local_cache.folder_path()).unwrap().with_credential_provider(|_, _| Ok(Auth::bearer("still-expired")));
+        let url = server.url("/rejected");
+        assert!(rejecting.get_local_file_path(&url).is_err());
+        assert_eq!(server.request_count(), 5);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_refreshed_credentials_kept_per_caller() {
+        let server = TestServer::start(|req| match req.header("Authorization") {
+            Some("Bearer renewed-a") => TestResponse::ok(b"content of a"),
+            Some("Bearer b") => TestResponse::ok(b"content of b"),
+            _ => TestResponse::status(401, b"unauthorized"),
+        });
+        let calls = Arc::new(AtomicUsize::new(0));
+        let th_calls = calls.clone();
+        let local_cache = TempCache::with("auth_refresh_callers", |c| {
+            c.with_credential_provider(move |_, _| {
+                th_calls.fetch_add(1, Ordering::SeqCst);
+                Ok(Auth::bearer("renewed-a"))
+            })
+        });
+        let fetch = |path: &str, token: &str| {
+            let url = server.url(path);
+            let p = local_cache.get_local_file_path_with_options(&url, &url, &FetchOptions::new().with_token(token)).unwrap();
+            fs::read_to_string(p).unwrap()
+        };
+
+        assert_eq!(fetch("/a1", "a"), "content of a");
+        assert_eq!(server.request_count(), 2);
+        //Another caller keeps its own token
+        assert_eq!(fetch("/b1", "b"), "content of b");
+        assert_eq!(server.request_count(), 3);
+        //The caller whose token was rejected starts with the refreshed one
+        assert_eq!(fetch("/a2", "a"), "content of a");
+        assert_eq!(server.request_count(), 4);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_credential_provider_on_forbidden_async() {
+        let server = expiring_token_server();
+        let local_cache = TempCache::with("auth_refresh_async", |c| {
+            c.with_credential_provider(|url, status| {
+                assert!(url.ends_with("/forbidden"));
+                assert_eq!(status, 403);
+                Ok(Auth::bearer("renewed"))
+            })
+        });
+
+        let p = local_cache.get_local_file_path_with_name_token_async(&server.url("/forbidden"), "forbidden", Some("old")).await.unwrap();
+
+        assert_eq!(fs::read(p).unwrap(), b"protected");
+        assert_eq!(server.request_count(), 2);
+        local_cache.get_local_file_path_with_name_token_async(&server.url("/forbidden"), "again", Some("old")).await.unwrap();
+        assert_eq!(server.request_count(), 3);
+        //The refreshed credentials belong to the instance that refreshed them
+        let other = BTCache::with_dir(local_cache.folder_path()).unwrap();
+        assert!(other.get_local_file_path_with_name_token_async(&server.url("/forbidden"), "other", Some("old")).await.is_err());
+    }
+
+    #[test]
+    fn test_auth_debug_hides_secrets() {
+        assert_eq!(format!("{:?}", Auth::basic("user", Some("secret"))), "Auth::Basic(user:***)");
+        assert!(!format!("{:?}", Auth::bearer("secret")).contains("secret"));
+    }
+}
@@ -0,0 +1,420 @@
+//bt-file-cache: command line tool to inspect and manage a BTCache folder.
+//The folder is the one used by BTCache::new (get_local_usr_data_path(<app>, "cache") or BT_FILE_CACHE_DIR) unless --dir is given.
+use std::{env, error::Error, process::ExitCode, time::{Duration, SystemTime}};
+
+use bt_file_cache::{
+    cache::{BTCache, CacheLocation},
+    maintenance::VerifyStatus,
+    metadata::{CacheEntry, EntryFilter},
+    options::FetchOptions,
+};
+use bt_logger::{LogLevel, LogTarget, build_logger_env};
+#[cfg(feature = "server")]
+use bt_file_cache::server::CacheServer;
+
+const USAGE: &str = "Usage: bt-file-cache [--app <name> [--location data|cache] | --dir <folder>] <command> [arguments]
+
+Commands:
+    get <url> [--name <name>] [--token <token>]       Return the cached file path, downloading the file if needed
+    fetch <url> [--name <name>] [--token <token>]     Same as get
+    list [--prefix <key>] [--min-size <bytes>] [--max-size <bytes>] [--min-age <secs>] [--max-age <secs>]
+                                                      List the cached entries
+    show <url|name>                                   Show the metadata of an entry
+    invalidate <url|name>                             Remove an entry
+    refresh <url> [--name <name>] [--token <token>]   Revalidate or download again an entry
+    purge-expired                                     Remove the entries whose expiry has passed
+    verify                                            Check the cached files against their recorded digest
+    size                                              Total size of the cached files
+    path                                              Show the cache folder
+    serve [--listen <addr>]                           Serve the cache over HTTP (default 127.0.0.1:8080). Requires the server feature
+
+Options:
+    --app <name>     Application folder name passed to BTCache::new (default: none)
+    --location <l>   data (default) or cache: user data or user cache directory of the platform
+    --dir <folder>   Cache folder, overrides --app and the BT_FILE_CACHE_DIR environment variable";
+
+#[cfg(feature = "server")]
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+
+fn main() -> ExitCode {
+    build_logger_env("BACHUETECH", "BT FILE CACHE CLI", LogLevel::ERROR, LogTarget::NONE, None);
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (folder, command) = match parse_args(&args) {
+        Ok(Cli::Help) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(Cli::Run { folder, command }) => (folder, command),
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match folder.open().and_then(|cache| command.run(cache)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+//Parsed command line
+#[derive(Debug, PartialEq)]
+enum Cli {
+    Help,
+    Run { folder: Folder, command: Command },
+}
+
+//Cache folder: --dir, or the data or cache directory of --app (--location)
+#[derive(Debug, PartialEq)]
+enum Folder {
+    Dir(String),
+    Data(Option<String>),
+    Cache(Option<String>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Get { url: String, name: Option<String>, token: Option<String> },
+    Refresh { url: String, name: Option<String>, token: Option<String> },
+    List(ListFilter),
+    Show(String),
+    Invalidate(String),
+    PurgeExpired,
+    Verify,
+    Size,
+    Path,
+    Serve { listen: Option<String> },
+}
+
+//Filter of the list command, sizes in bytes and ages in seconds
+#[derive(Debug, Default, PartialEq)]
+struct ListFilter {
+    prefix: Option<String>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    min_age: Option<u64>,
+    max_age: Option<u64>,
+}
+
+//Parse the command line arguments (without the program name). -h or --help anywhere asks for the usage
+fn parse_args(args: &[String]) -> Result<Cli, String> {
+    let mut app = None;
+    let mut dir = None;
+    let mut location = None;
+    let mut positional = Vec::new();
+    let mut options = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Cli::Help);
+        }
+        if let Some(opt) = arg.strip_prefix("--") {
+            let value = iter.next().ok_or_else(|| format!("Missing value for option --{}", opt))?.clone();
+            match opt {
+                "app" => app = Some(value),
+                "dir" => dir = Some(value),
+                "location" => location = Some(value),
+                _ => options.push((opt.to_owned(), value)),
+            }
+        } else {
+            positional.push(arg.clone());
+        }
+    }
+
+    let folder = match (dir, location.as_deref()) {
+        (Some(dir), _) => Folder::Dir(dir),
+        (None, None | Some("data")) => Folder::Data(app),
+        (None, Some("cache")) => Folder::Cache(app),
+        (None, Some(other)) => return Err(format!("Invalid location '{}'. Use data or cache", other)),
+    };
+    let (name, positional) = positional.split_first().ok_or_else(|| "Missing command".to_owned())?;
+    let mut args = Arguments { command: name, positional, target_taken: false, options };
+    let command = match name.as_str() {
+        "get" | "fetch" => Command::Get { url: args.target()?, name: args.option("name"), token: args.option("token") },
+        "refresh" => Command::Refresh { url: args.target()?, name: args.option("name"), token: args.option("token") },
+        "list" => Command::List(ListFilter {
+            prefix: args.option("prefix"),
+            min_size: args.number_option("min-size")?,
+            max_size: args.number_option("max-size")?,
+            min_age: args.number_option("min-age")?,
+            max_age: args.number_option("max-age")?,
+        }),
+        "show" => Command::Show(args.target()?),
+        "invalidate" => Command::Invalidate(args.target()?),
+        "purge-expired" => Command::PurgeExpired,
+        "verify" => Command::Verify,
+        "size" => Command::Size,
+        "path" => Command::Path,
+        "serve" => Command::Serve { listen: args.option("listen") },
+        other => return Err(format!("Unknown command: {}", other)),
+    };
+    args.finish()?;
+    Ok(Cli::Run { folder, command })
+}
+
+//Arguments of a command, consumed while building it: anything the command does not take is rejected by finish
+struct Arguments<'a> {
+    command: &'a str,
+    positional: &'a [String],
+    target_taken: bool,
+    options: Vec<(String, String)>,
+}
+
+impl Arguments<'_> {
+    fn option(&mut self, name: &str) -> Option<String> {
+        let index = self.options.iter().position(|(n, _)| n == name)?;
+        Some(self.options.remove(index).1)
+    }
+
+    fn number_option(&mut self, name: &str) -> Result<Option<u64>, String> {
+        match self.option(name) {
+            Some(v) => Ok(Some(v.parse().map_err(|_| format!("Invalid number for --{}: {}", name, v))?)),
+            None => Ok(None),
+        }
+    }
+
+    fn target(&mut self) -> Result<String, String> {
+        self.target_taken = true;
+        match self.positional {
+            [t] => Ok(t.clone()),
+            _ => Err(format!("Command '{}' expects exactly one URL or name", self.command)),
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        if let Some((name, _)) = self.options.first() {
+            return Err(format!("Unknown option --{} for command '{}'", name, self.command));
+        }
+        match self.positional.first() {
+            Some(extra) if !self.target_taken => Err(format!("Command '{}' takes no argument: {}", self.command, extra)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Folder {
+    fn open(&self) -> Result<BTCache, Box<dyn Error>> {
+        match self {
+            Folder::Dir(dir) => BTCache::with_dir(dir),
+            Folder::Data(app) => BTCache::new(app.as_deref()),
+            Folder::Cache(app) => BTCache::new_with_location(app.as_deref(), CacheLocation::Cache),
+        }
+    }
+}
+
+impl ListFilter {
+    fn entry_filter(&self) -> EntryFilter {
+        let mut filter = EntryFilter::new();
+        if let Some(p) = &self.prefix {
+            filter = filter.with_key_prefix(p);
+        }
+        if let Some(s) = self.min_size {
+            filter = filter.with_min_size(s);
+        }
+        if let Some(s) = self.max_size {
+            filter = filter.with_max_size(s);
+        }
+        if let Some(a) = self.min_age {
+            filter = filter.with_min_age(Duration::from_secs(a));
+        }
+        if let Some(a) = self.max_age {
+            filter = filter.with_max_age(Duration::from_secs(a));
+        }
+        filter
+    }
+}
+
+fn fetch_options(token: Option<&str>) -> FetchOptions {
+    match token {
+        Some(t) => FetchOptions::new().with_token(t),
+        None => FetchOptions::new(),
+    }
+}
+
+impl Command {
+    fn run(&self, cache: BTCache) -> Result<(), Box<dyn Error>> {
+        match self {
+            Command::Path => println!("{}", cache.folder_path().display()),
+            Command::Get { url, name, token } => {
+                let name = name.as_deref().unwrap_or(url);
+                println!("{}", cache.get_local_file_path_with_options(url, name, &fetch_options(token.as_deref()))?);
+            }
+            Command::Refresh { url, name, token } => {
+                let name = name.as_deref().unwrap_or(url);
+                println!("{}", cache.refresh_cache_with_options(url, name, &fetch_options(token.as_deref()))?);
+            }
+            Command::List(filter) => {
+                let entries = cache.list_entries(&filter.entry_filter())?;
+                println!("{:>12}  {:>10}  {:>10}  {:>10}  KEY", "SIZE", "AGE", "ACCESSED", "EXPIRES");
+                for e in &entries {
+                    println!("{:>12}  {:>10}  {:>10}  {:>10}  {}", e.size, format_age(e.fetched_at), format_age(e.last_access), format_expiry(e), e.key);
+                }
+                println!("{} entries", entries.len());
+            }
+            Command::Show(key) => {
+                let entry = cache.entry_info(key)?.ok_or_else(|| format!("Entry not found: {}", key))?;
+                print_entry(&entry);
+            }
+            Command::Invalidate(key) => {
+                cache.invalidate_cache(key)?;
+                println!("Removed {}", key);
+            }
+            Command::PurgeExpired => {
+                println!("Removed {} expired entries", cache.purge_expired()?);
+            }
+            Command::Verify => {
+                let mut failed = 0;
+                for v in cache.verify_entries()? {
+                    let status = match &v.status {
+                        VerifyStatus::Valid => "OK".to_owned(),
+                        VerifyStatus::Unverified => "UNVERIFIED".to_owned(),
+                        VerifyStatus::Corrupted(reason) => {
+                            failed += 1;
+                            format!("CORRUPTED ({})", reason)
+                        }
+                    };
+                    println!("{:<12} {}", status, v.entry.key);
+                }
+                if failed > 0 {
+                    return Err(format!("{} corrupted entries", failed).into());
+                }
+            }
+            Command::Serve { listen } => return serve(cache, listen.as_deref()),
+            Command::Size => {
+                println!("{} bytes", cache.total_size()?);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "server")]
+fn serve(cache: BTCache, listen: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let server = CacheServer::bind(std::sync::Arc::new(cache), listen.unwrap_or(DEFAULT_LISTEN))?;
+    println!("Serving cache on http://{}/fetch?url=<url>", server.local_addr()?);
+    server.run();
+    Ok(())
+}
+
+#[cfg(not(feature = "server"))]
+fn serve(_cache: BTCache, _listen: Option<&str>) -> Result<(), Box<dyn Error>> {
+    Err("bt-file-cache was built without the server feature".into())
+}
+
+fn print_entry(entry: &CacheEntry) {
+    let none = || "-".to_owned();
+    println!("Key:           {}", entry.key);
+    println!("URL:           {}", entry.url.clone().unwrap_or_else(none));
+    println!("Path:          {}", entry.path.display());
+    println!("Size:          {} bytes", entry.size);
+    println!("Fetched:       {} ago", format_age(entry.fetched_at));
+    println!("Last access:   {} ago", format_age(entry.last_access));
+    println!("Expires:       {}", format_expiry(entry));
+    println!("ETag:          {}", entry.etag.clone().unwrap_or_else(none));
+    println!("Content-Type:  {}", entry.content_type.clone().unwrap_or_else(none));
+}
+
+fn format_age(time: SystemTime) -> String {
+    format_duration(SystemTime::now().duration_since(time).unwrap_or_default())
+}
+
+fn format_expiry(entry: &CacheEntry) -> String {
+    match entry.expires_at {
+        None => "-".to_owned(),
+        Some(e) => match e.duration_since(SystemTime::now()) {
+            Ok(left) => format!("in {}", format_duration(left)),
+            Err(_) => "expired".to_owned(),
+        },
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m{}s", s / 60, s % 60),
+        s if s < 86400 => format!("{}h{}m", s / 3600, (s % 3600) / 60),
+        s => format!("{}d{}h", s / 86400, (s % 86400) / 3600),
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli, String> {
+        parse_args(&args.split_whitespace().map(str::to_owned).collect::<Vec<_>>())
+    }
+
+    fn command(args: &str) -> Command {
+        match parse(args) {
+            Ok(Cli::Run { command, .. }) => command,
+            other => panic!("{:?} parsed as {:?}", args, other),
+        }
+    }
+
+    #[test]
+    fn test_help() {
+        assert_eq!(parse("--help"), Ok(Cli::Help));
+        assert_eq!(parse("-h"), Ok(Cli::Help));
+        assert_eq!(parse("list --prefix x -h"), Ok(Cli::Help));
+        assert_eq!(parse("unknown --help"), Ok(Cli::Help));
+    }
+
+    #[test]
+    fn test_commands() {
+        let url = "http://127.0.0.1:9/a.json";
+        assert_eq!(command(&format!("get {}", url)), Command::Get { url: url.to_owned(), name: None, token: None });
+        assert_eq!(command(&format!("fetch {} --name a --token t", url)), Command::Get { url: url.to_owned(), name: Some("a".to_owned()), token: Some("t".to_owned()) });
+        assert_eq!(command(&format!("refresh --token t {}", url)), Command::Refresh { url: url.to_owned(), name: None, token: Some("t".to_owned()) });
+        assert_eq!(command("list"), Command::List(ListFilter::default()));
+        assert_eq!(command("list --prefix img/ --min-size 10 --max-age 60"),
+            Command::List(ListFilter { prefix: Some("img/".to_owned()), min_size: Some(10), max_age: Some(60), ..Default::default() }));
+        assert_eq!(command("show a"), Command::Show("a".to_owned()));
+        assert_eq!(command("invalidate a"), Command::Invalidate("a".to_owned()));
+        assert_eq!(command("purge-expired"), Command::PurgeExpired);
+        assert_eq!(command("verify"), Command::Verify);
+        assert_eq!(command("size"), Command::Size);
+        assert_eq!(command("path"), Command::Path);
+        assert_eq!(command("serve --listen 0.0.0.0:9000"), Command::Serve { listen: Some("0.0.0.0:9000".to_owned()) });
+    }
+
+    #[test]
+    fn test_folders() {
+        let folder = |args: &str| match parse(args) {
+            Ok(Cli::Run { folder, .. }) => folder,
+            other => panic!("{:?} parsed as {:?}", args, other),
+        };
+        assert_eq!(folder("path"), Folder::Data(None));
+        assert_eq!(folder("--app demo path"), Folder::Data(Some("demo".to_owned())));
+        assert_eq!(folder("--app demo --location cache path"), Folder::Cache(Some("demo".to_owned())));
+        assert_eq!(folder("--app demo --location cache --dir /tmp/c path"), Folder::Dir("/tmp/c".to_owned()));
+    }
+
+    #[test]
+    fn test_errors() {
+        for args in [
+            "",
+            "--app demo",
+            "unknown",
+            "get",
+            "get a b",
+            "show",
+            "path extra",
+            "list --min-size ten",
+            "list --token t",
+            "get a --name",
+            "--location tmp path",
+        ] {
+            assert!(parse(args).is_err(), "{:?} should be rejected", args);
+        }
+    }
+}
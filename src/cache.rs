@@ -1,34 +1,69 @@
-use std::{env, error::Error, fs::{self, remove_file}, io::Write, path::PathBuf, time::Duration};
+use std::{error::Error, fs::{self, remove_file}, path::{Path, PathBuf}, process, sync::{Arc, atomic::{AtomicU64, Ordering}}};
 
 use base64::{Engine, engine::general_purpose};
-use bt_logger::{get_error, log_error, log_verbose};
-use once_cell::sync;
-use reqwest::{Client, Url};
+use bt_logger::{get_error, log_error, log_verbose, log_warning};
 use sha3::{Digest, Sha3_512};
 
-use crate::folder_manager::get_local_usr_data_path;
+#[cfg(feature = "async")]
+use crate::fs_io::nonblocking;
+use crate::{auth::{Auth, CredentialProvider, RefreshedCredentials}, download::{Download, Downloader}, folder_manager::{DirKind, DirResolver}, key::KeyStrategy, metadata::EntryMetadata, options::FetchOptions, permissions::{self, PermissionPolicy}, stats::{CacheStats, MetricsRecorder, StatsCounters}};
+
+///Platform directory holding the cache folder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheLocation {
+    ///User data directory (get_local_usr_data_path), e.g. ~/.local/share/<app>/cache. Used by BTCache::new
+    #[default]
+    Data,
+    ///User cache directory (get_local_usr_cache_path), e.g. ~/.cache/<app>, ~/Library/Caches/<app> or %LOCALAPPDATA%\<app>\Cache.
+    ///Preferred for downloaded files since backup tools and cleaners treat it as disposable
+    Cache,
+}
 
-static DEFAULT_USER_AGENT: sync::Lazy<String> = sync::Lazy::new(||{
-    format!("Mozilla/5.0 ({}; {}; {}) {}/{}", env::consts::FAMILY, env::consts::OS, env::consts::ARCH, option_env!("CARGO_PKG_NAME").unwrap_or("bt_file_cache"), option_env!("CARGO_PKG_VERSION").unwrap_or("0.0.1b"))
-});
+///Outcome of BTCache::migrate_from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    ///migrated: Cached files moved into the cache (with their metadata and Vary records)
+    pub migrated: usize,
+    ///skipped: Cached files already present in the cache. The cache copy is kept and the old copy is discarded
+    pub skipped: usize,
+}
 
-static HTTP_CLIENT: sync::Lazy<Client> = sync::Lazy::new(||{ 
-        const CLIENT_REQUEST_TIMEOUT: u64 = 10;
-        if let Ok(c) = reqwest::Client::builder()
-                                        .timeout(Duration::from_secs(CLIENT_REQUEST_TIMEOUT))
-                                        .user_agent(DEFAULT_USER_AGENT.clone())
-                                        .build(){
-            c
-        }else{
-            reqwest::Client::new()
-        }
-    });
+///Environment variable overriding the folder used by BTCache::new
+pub const CACHE_DIR_ENV: &str = "BT_FILE_CACHE_DIR";
+
+//Sequence used to give every temporary file (downloads in progress, metadata updates) its own name
+static TEMP_FILE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+//Unique temporary path next to the given file. The name never collides with a cache file name (base64 URL safe has no dots)
+pub(crate) fn temp_path(int_file_path: &Path, extension: &str) -> PathBuf {
+    let seq = TEMP_FILE_SEQ.fetch_add(1, Ordering::Relaxed);
+    int_file_path.with_extension(format!("{}-{}.{}", process::id(), seq, extension))
+}
+
+//True when both paths resolve to the same directory. Compares the canonical paths, so different spellings of one folder
+//(case on Windows and macOS, symbolic links, '..') are detected
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
 
 ///BTCache provides a caching mechanism for downloading and storing files from URLs. 
 ///It generates SHA3-512 hashes of URLs to create unique file names and manages local storage of cached files.
 pub struct BTCache{
     ///folder_path: A PathBuf containing the directory path where cached files are stored.
-    folder_path: PathBuf,
+    pub(crate) folder_path: PathBuf,
+    ///stats: Activity counters of this instance
+    pub(crate) stats: Arc<StatsCounters>,
+    ///permissions: Modes applied to the folders and files created by this instance
+    pub(crate) permissions: PermissionPolicy,
+    ///key_strategy: Derivation of the cache key from the URL or name
+    pub(crate) key_strategy: KeyStrategy,
+    ///credentials: Provider of refreshed credentials when a download is rejected (401/403)
+    pub(crate) credentials: Option<CredentialProvider>,
+    ///refreshed_auth: Credentials returned by the provider, reused for the later requests sending the rejected credentials to the same server
+    pub(crate) refreshed_auth: RefreshedCredentials,
 }
 
 impl BTCache {
@@ -41,360 +76,344 @@ impl BTCache {
     /// 
     /// #Returns"
     ///     * Result<Self, Box<dyn Error>>: Returns a BTCache instance on success, or an error if the local data path cannot be determined.
+    /// 
+    /// When the environment variable BT_FILE_CACHE_DIR is set (and not empty) its value is used as cache folder instead,
+    /// and app_folder_name is ignored.
     pub fn new(app_folder_name: Option<&str>) -> Result<Self, Box<dyn Error>>{
-        let local_path = get_local_usr_data_path(app_folder_name, Some("cache"), true)?;
-        Ok(
-            Self { folder_path: PathBuf::from(local_path) }
-        )
+        Self::new_with_resolver(app_folder_name, CacheLocation::Data, &DirResolver::default())
     }
 
-    ///Generate a Sha3_512 hash for the given String encoded with base64 URLSAFE no padding
-    ///This ensures a consistent, unique identifier for each URL that can be safely used as a filename.
+    ///Constructor
+    /// Creates a new BTCache instance in the data or cache location of the platform (see CacheLocation).
+    /// When CacheLocation::Cache is selected, entries found in the data location used by previous versions (BTCache::new)
+    /// are moved to the new folder.
+    /// The environment variable BT_FILE_CACHE_DIR overrides the location, as in BTCache::new.
     /// 
-    /// #Parameters
-    ///     * input: A string slice containing the URL to be hashed.
+    /// #Parameters:
+    ///     * app_folder_name: An optional string slice that specifies the application folder name.
+    ///     * location: Platform directory used for the cache
     /// 
     /// #Returns
-    ///     * String: A base64 URL-safe encoded SHA3-512 hash of the input string.
-    fn get_hash_string_base64(input: &str) -> String {
-        let mut hasher = Sha3_512::new();
-        hasher.update(input.as_bytes());
-        let result = hasher.finalize();
-        general_purpose::URL_SAFE_NO_PAD.encode(result)
+    ///     * Result<Self, Box<dyn Error>>: Returns a BTCache instance on success, or an error if the folder cannot be determined or created.
+    pub fn new_with_location(app_folder_name: Option<&str>, location: CacheLocation) -> Result<Self, Box<dyn Error>>{
+        Self::new_with_resolver(app_folder_name, location, &DirResolver::default())
     }
 
-    ///ASYNC Helper Method. Downloads a file from the specified URL and saves it to the given file path.
-    ///Uses reqwest for HTTP requests and writes the response bytes directly to a file.
+    ///Constructor
+    /// Same as BTCache::new_with_location, resolving the platform directories (and reading BT_FILE_CACHE_DIR) through the given resolver
+    /// instead of the process environment. Useful to control the folder of one instance without global state.
+    /// 
+    /// #Parameters:
+    ///     * app_folder_name: An optional string slice that specifies the application folder name.
+    ///     * location: Platform directory used for the cache
+    ///     * resolver: Resolver of the platform directories
     /// 
-    ///#Parameters
-    /// * url: A string slice containing the URL to download.
-    /// * int_file_path: A reference to a PathBuf specifying where the downloaded file should be saved.
-    ///#Returns
-    /// *   Result<(), Box<dyn Error>>: Returns Ok(()) on successful download, or an error if the download or file creation fails.
-    async fn download_file_async(url: &str, int_file_path: &PathBuf, token: Option<&str>) -> Result<(), Box<dyn Error>>{
-        let parsed_url = Url::parse(url)?;
-        let mut request_builder = HTTP_CLIENT.get(parsed_url);
-        if  token.is_some(){
-            request_builder = request_builder.bearer_auth(token.unwrap());
+    /// #Returns
+    ///     * Result<Self, Box<dyn Error>>: Returns a BTCache instance on success, or an error if the folder cannot be determined or created.
+    pub fn new_with_resolver(app_folder_name: Option<&str>, location: CacheLocation, resolver: &DirResolver) -> Result<Self, Box<dyn Error>>{
+        if let Some(dir) = Self::dir_override(resolver.var(CACHE_DIR_ENV)) {
+            return Self::with_dir(dir);
         }
-        let response = request_builder.send().await?; 
-        if response.status().is_client_error() || response.status().is_server_error(){
-            return Err(get_error!("download_file_async","Request Error: {}", response.status()).into())
+        let data_folder = resolver.path(DirKind::Data, app_folder_name, Some("cache"), false)?;
+        if location == CacheLocation::Data {
+            return Self::with_dir(data_folder);
         }
 
-        let bytes = response.bytes().await?;
-        let mut file = fs::File::create(int_file_path)?;
-        file.write_all(&bytes)?;
-
-        Ok(())
+        let cache = Self::with_dir(resolver.path(DirKind::Cache, app_folder_name, None, false)?)?;
+        let data_folder = PathBuf::from(data_folder);
+        //On case insensitive file systems the data and cache locations can be the same folder (e.g. ...\Data and ...\data)
+        if data_folder.is_dir() && !same_dir(&data_folder, &cache.folder_path) {
+            match cache.migrate_from(&data_folder) {
+                Ok(r) => log_verbose!("new_with_resolver","Moved {} cached files from '{:?}' to '{:?}', {} already cached", r.migrated, data_folder, cache.folder_path, r.skipped),
+                Err(e) => log_error!("new_with_resolver","Unable to move cached files from '{:?}': {}", data_folder, e),
+            }
+        }
+        Ok(cache)
     }
 
-    ///ASYNC Function that attempts to retrieve a local file path for a given URL. The method:
-    ///The method handles both cases where the file path check fails entirely (logging an error and attempting to download) 
-    ///and where the file doesn't exist at the expected location (performing a download).
+    ///Move the entries (files, metadata and Vary records) stored in another cache folder into this cache.
+    /// Entries already present in this cache are kept and the old copies (with their metadata and Vary records) are discarded, with a warning.
+    /// The old folder is removed when it ends up empty.
     /// 
     /// #Parameters:
-    ///     * url: A string slice containing the URL of the file to retrieve from cache.
+    ///     * dir: Folder of the other cache
     /// 
-    /// #Returns:
-    ///     * Result<String, Box<dyn Error>>: Returns the full local file path as a string on success, or an error if:
-    ///                                         The file path cannot be retrieved due to invalid Unicode
-    ///                                         File operations fail during download or path checking    
-    pub async fn get_local_file_path_async(&self, url: &str) -> Result<String,Box<dyn Error>> {
-        self.get_local_file_path_with_name_token_async(url, url, None).await
-    } 
-
-    ///ASYNC Function that attempts to retrieve a local file path for a given URL. The method:
-    ///The method handles both cases where the file path check fails entirely (logging an error and attempting to download) 
-    ///and where the file doesn't exist at the expected location (performing a download).
+    /// #Returns
+    ///     * Result<MigrationReport, Box<dyn Error>>: Number of files moved and skipped (none when dir is the folder of this cache), or an error if the folder cannot be read.
+    pub fn migrate_from<P: AsRef<Path>>(&self, dir: P) -> Result<MigrationReport, Box<dyn Error>>{
+        let mut report = MigrationReport::default();
+        //Migrating a folder into itself would discard every entry as "already present"
+        if same_dir(dir.as_ref(), &self.folder_path) {
+            return Ok(report);
+        }
+        for dir_entry in fs::read_dir(dir.as_ref())? {
+            let dir_entry = dir_entry?;
+            let name = dir_entry.file_name();
+            let name_str = name.to_string_lossy();
+            //Cache files, their metadata and Vary records only, temporary files of downloads in progress are left alone
+            let record = name_str.strip_suffix(".meta").or_else(|| name_str.strip_suffix(".vary"));
+            let is_entry = !record.unwrap_or(&name_str).contains('.');
+            if !is_entry || !dir_entry.file_type()?.is_file() {
+                continue;
+            }
+            let target = self.folder_path.join(&name);
+            if target.exists() {
+                log_warning!("migrate_from","'{:?}' is already cached in '{:?}', the old copy is discarded", name, self.folder_path);
+                remove_file(dir_entry.path())?;
+                if record.is_none() {
+                    report.skipped += 1;
+                }
+                continue;
+            }
+            if fs::rename(dir_entry.path(), &target).is_err() {
+                //Different file systems: copy then remove
+                fs::copy(dir_entry.path(), &target)?;
+                remove_file(dir_entry.path())?;
+            }
+            if record.is_none() {
+                report.migrated += 1;
+            }
+        }
+        let _ = fs::remove_dir(dir.as_ref());
+        Ok(report)
+    }
+
+    ///Constructor
+    /// Creates a new BTCache instance storing its files in the given directory (e.g. a dedicated volume, a project folder or a test temporary folder).
+    /// The directory is created if it does not exist.
     /// 
     /// #Parameters:
-    ///     * url: A string slice containing the URL of the file to retrieve from cache.
-    ///     * file_name: desire file name or file id. Useful when file may associuted to multiple URLs
+    ///     * dir: Directory where the cached files are stored.
     /// 
-    /// #Returns:
-    ///     * Result<String, Box<dyn Error>>: Returns the full local file path as a string on success, or an error if:
-    ///                                         The file path cannot be retrieved due to invalid Unicode
-    ///                                         File operations fail during download or path checking    
-    pub async fn get_local_file_path_with_name_async(&self, url: &str, file_name: &str) -> Result<String,Box<dyn Error>> {
-        self.get_local_file_path_with_name_token_async(url, file_name, None).await
-    }    
+    /// #Returns
+    ///     * Result<Self, Box<dyn Error>>: Returns a BTCache instance on success, or an error if the directory cannot be created.
+    pub fn with_dir<P: AsRef<Path>>(dir: P) -> Result<Self, Box<dyn Error>>{
+        let folder_path = dir.as_ref().to_path_buf();
+        let permissions = PermissionPolicy::default();
+        permissions::create_dir_all(&folder_path, permissions.dir_mode).map_err(|e| get_error!("with_dir","Unable to create cache folder '{:?}': {}", folder_path, e))?;
+        let cache = Self { folder_path, stats: Arc::default(), permissions, key_strategy: KeyStrategy::default(), credentials: None, refreshed_auth: RefreshedCredentials::default() };
+        cache.check_folder_permissions()?;
+        Ok(cache)
+    }
 
-    ///ASYNC Function that attempts to retrieve a local file path for a given URL. The method:
-    ///The method handles both cases where the file path check fails entirely (logging an error and attempting to download) 
-    ///and where the file doesn't exist at the expected location (performing a download).
+    ///Set the permission policy used for the folders and files created by this instance (PermissionPolicy::default() when not set).
+    /// The folder mode of the policy is applied to the cache folder right away.
     /// 
     /// #Parameters:
-    ///     * url: A string slice containing the URL of the file to retrieve from cache.
-    ///     * file_name: desire file name or file id. Useful when file may associuted to multiple URLs
-    ///     * Token: Access token to be use to access the URL resource
+    ///     * policy: Modes of the folders and files
     /// 
-    /// #Returns:
-    ///     * Result<String, Box<dyn Error>>: Returns the full local file path as a string on success, or an error if:
-    ///                                         The file path cannot be retrieved due to invalid Unicode
-    ///                                         File operations fail during download or path checking    
-    pub async fn get_local_file_path_with_name_token_async(&self, url: &str, file_name: &str, token: Option<&str>) -> Result<String,Box<dyn Error>> {
-        let int_file_path = self.get_file(file_name); //self.folder_path.join(Self::get_hash_string_base64(file_name));
-
-        let path_check = int_file_path.try_exists();
-        if path_check.is_err() {
-            log_error!("get_local_file_path","Issue finding file '{:?}' trying downloading again",int_file_path);
-            Self::download_file_async(url, &int_file_path, token).await?;
-        }else{
-            if !path_check.unwrap(){
-                //File not found
-                Self::download_file_async(url, &int_file_path, token).await?;
-            }
-        }        
-
-        if let Some(full_path) = int_file_path.to_str(){
-            return Ok(full_path.to_owned())
-        }else{
-            return Err(get_error!("get_local_file_path","Unable to retrieve cached file path. Invalid Unicode Path").into())
-        }
-    }        
+    /// #Returns
+    ///     * Result<Self, Box<dyn Error>>: Returns the BTCache instance, or an error if the mode of the cache folder cannot be changed.
+    pub fn with_permissions(mut self, policy: PermissionPolicy) -> Result<Self, Box<dyn Error>>{
+        permissions::set_mode(&self.folder_path, policy.dir_mode)?;
+        self.permissions = policy;
+        Ok(self)
+    }
 
-    ///Async function that encodes the file bytes using standard base64 encoding
+    ///Set the derivation of the cache keys (KeyStrategy::raw() when not set: the URL or name is used verbatim).
+    /// Entries cached with another strategy are not found anymore when their derived key changes.
     /// 
-    ///#Parameters
-    ///     * url: A string slice (&str) containing the URL of the file to retrieve
+    /// #Parameters:
+    ///     * strategy: Key derivation, e.g. KeyStrategy::normalized().ignore_query_params(&["utm_*"])
     /// 
-    ///#Returns
-    ///    Result<String, Box<dyn Error>>: Returns a String containing the base64-encoded file data on success, or an error if:
-    ///    The local file path cannot be determined
-    ///    The file cannot be read from the local cache
-    ///    Base64 encoding fails
-    pub async fn get_file_data_base64_async(&self, url: &str) -> Result<String,Box<dyn Error>> {
-        let full_file_path = self.get_local_file_path_with_name_token_async(url, url, None).await?;
-        let file_data_bytes = fs::read(full_file_path)?;
-        Ok(general_purpose::STANDARD.encode(file_data_bytes))
-    }    
+    /// #Returns
+    ///     * Self: The BTCache instance using the strategy
+    pub fn with_key_strategy(mut self, strategy: KeyStrategy) -> Self {
+        self.key_strategy = strategy;
+        self
+    }
 
-    ///Async function that encodes the file bytes using standard base64 encoding
+    ///Set the provider of refreshed credentials. When a download is rejected with 401 Unauthorized or 403 Forbidden,
+    /// the provider is called and the request is retried once with the credentials it returns (sync and async functions).
+    /// The returned credentials are kept for the later requests sending the same rejected credentials (or none) to the same
+    /// server (scheme, host and port), until they are rejected in turn and the provider is called again. Requests with other
+    /// credentials always send their own. Credentials supplied by an Auth::callback are not replaced.
+    /// The async functions call the provider on a blocking thread, so it may block (e.g. to request a new token).
     /// 
-    ///#Parameters
-    ///     * url: A string slice (&str) containing the URL of the file to retrieve
-    ///     * file_name: desire file name or file id. Useful when file may associuted to multiple URLs
+    /// #Parameters:
+    ///     * provider: Closure receiving the URL and the response status and returning the new credentials, e.g. a renewed bearer token
     /// 
-    ///#Returns
-    ///    Result<String, Box<dyn Error>>: Returns a String containing the base64-encoded file data on success, or an error if:
-    ///    The local file path cannot be determined
-    ///    The file cannot be read from the local cache
-    ///    Base64 encoding fails
-    pub async fn get_file_data_base64_with_name_async(&self, url: &str, file_name: &str) -> Result<String,Box<dyn Error>> {
-        let full_file_path = self.get_local_file_path_with_name_token_async(url, file_name, None).await?;
-        let file_data_bytes = fs::read(full_file_path)?;
-        Ok(general_purpose::STANDARD.encode(file_data_bytes))
-    } 
-
-    ///Async function that encodes the file bytes using standard base64 encoding
+    /// #Returns
+    ///     * Self: The BTCache instance using the provider
+    pub fn with_credential_provider<F>(mut self, provider: F) -> Self
+    where
+        F: Fn(&str, u16) -> Result<Auth, Box<dyn Error>> + Send + Sync + 'static,
+    {
+        self.credentials = Some(Arc::new(provider));
+        self
+    }
+
+    ///Permission policy of this instance
+    pub fn permission_policy(&self) -> PermissionPolicy {
+        self.permissions
+    }
+
+    ///Check whether the cache folder can be written by its group or by any user (a warning is logged in that case),
+    /// which allows other users of a shared host to tamper with the cached files.
     /// 
-    ///#Parameters
-    ///     * url: A string slice (&str) containing the URL of the file to retrieve
-    ///     * file_name: desire file name or file id. Useful when file may associuted to multiple URLs
-    ///     * token: Access token to access the URL resource
+    /// #Returns
+    ///     * Result<bool, Box<dyn Error>>: true when the folder is group or world writable, or an error if the folder cannot be read.
+    pub fn check_folder_permissions(&self) -> Result<bool, Box<dyn Error>>{
+        let writable = permissions::is_group_or_world_writable(&self.folder_path)?;
+        if writable {
+            log_warning!("check_folder_permissions","Cache folder '{:?}' is writable by its group or other users", self.folder_path);
+        }
+        Ok(writable)
+    }
+
+    ///Directory where the cached files are stored
+    pub fn folder_path(&self) -> &Path {
+        &self.folder_path
+    }
+
+    //Cache folder requested through the environment (BT_FILE_CACHE_DIR), ignored when empty
+    fn dir_override(value: Option<String>) -> Option<PathBuf> {
+        value.filter(|v| !v.is_empty()).map(PathBuf::from)
+    }
+
+    ///Generate a Sha3_512 hash for the given String encoded with base64 URLSAFE no padding
+    ///This ensures a consistent, unique identifier for each URL that can be safely used as a filename.
     /// 
-    ///#Returns
-    ///    Result<String, Box<dyn Error>>: Returns a String containing the base64-encoded file data on success, or an error if:
-    ///    The local file path cannot be determined
-    ///    The file cannot be read from the local cache
-    ///    Base64 encoding fails
-    pub async fn get_file_data_base64_with_name_token_async(&self, url: &str, file_name: &str, token: Option<&str>) -> Result<String,Box<dyn Error>> {
-        let full_file_path = self.get_local_file_path_with_name_token_async(url, file_name, token).await?;
-log_verbose!("get_file_data_base64_with_name_token_async","Getting '{}' = '{}' . With AT {:?}",url,full_file_path, token);
-        let file_data_bytes = fs::read(full_file_path)?;
-        Ok(general_purpose::STANDARD.encode(file_data_bytes))
-    }        
-
-    ///Helper Method. Downloads a file from the specified URL and saves it to the given file path.
-    ///Uses reqwest for HTTP requests and writes the response bytes directly to a file.
+    /// #Parameters
+    ///     * input: A string slice containing the URL to be hashed.
     /// 
-    ///#Parameters
-    /// * url: A string slice containing the URL to download.
-    /// * int_file_path: A reference to a PathBuf specifying where the downloaded file should be saved.
-    ///#Returns
-    /// *   Result<(), Box<dyn Error>>: Returns Ok(()) on successful download, or an error if the download or file creation fails.
-    fn download_file(url: &str, int_file_path: &PathBuf) -> Result<(), Box<dyn Error>>{
-        let parsed_url = Url::parse(url)?;        
-        let download_response = reqwest::blocking::get(parsed_url)?;
-
-        if download_response.status().is_client_error() || download_response.status().is_server_error(){
-            return Err(get_error!("download_file_async","Request Error: {}", download_response.status()).into())
-        }
+    /// #Returns
+    ///     * String: A base64 URL-safe encoded SHA3-512 hash of the input string.
+    fn get_hash_string_base64(input: &str) -> String {
+        let mut hasher = Sha3_512::new();
+        hasher.update(input.as_bytes());
+        let result = hasher.finalize();
+        general_purpose::URL_SAFE_NO_PAD.encode(result)
+    }
 
-        let bytes = download_response.bytes()?;
-        let mut file = fs::File::create(int_file_path)?;
-        file.write_all(&bytes)?;
+    ///Returns a snapshot of the activity counters of this instance (hits, misses, downloads, revalidations, errors...)
+    pub fn stats(&self) -> CacheStats {
+        self.stats.snapshot()
+    }
+
+    ///Register (or remove with None) a recorder receiving every counter update, to publish the statistics to a metrics system
+    pub fn set_metrics_recorder(&self, recorder: Option<Arc<dyn MetricsRecorder>>) {
+        self.stats.set_recorder(recorder);
+    }
+
+    //Settings used by the downloads of this instance
+    pub(crate) fn downloader(&self) -> Downloader {
+        Downloader { permissions: self.permissions, credentials: self.credentials.clone(), refreshed_auth: self.refreshed_auth.clone() }
+    }
 
+    //Record a completed download (metadata and statistics). A failure to save the metadata only loses the metadata, the cached file is still valid
+    fn store_download(&self, int_file_path: &Path, file_name: &str, download: Download) -> Result<(), Box<dyn Error>>{
+        let metadata = match download {
+            Download::Modified(mut m) => {
+                self.stats.record_download(m.size);
+                self.stats.record_served(m.size);
+                m.key = file_name.to_owned();
+                m
+            },
+            //Keep the cached file, refresh the metadata with the validators/expiry of the 304 response
+            Download::NotModified(m) => {
+                let mut current = EntryMetadata::load(int_file_path).unwrap_or_default();
+                current.key = file_name.to_owned();
+                current.fetched_at = m.fetched_at;
+                current.last_access = m.last_access;
+                current.expires_at = m.expires_at;
+                current.etag = m.etag.or(current.etag);
+                current.last_modified = m.last_modified.or(current.last_modified);
+                self.stats.record_served(current.size);
+                current
+            },
+        };
+        if let Err(e) = metadata.save(int_file_path){
+            log_error!("store_download","Unable to save metadata of '{:?}': {}", int_file_path, e);
+        }
         Ok(())
     }
 
-    //Build file name to standarize it
-    fn get_file(&self, file_name: &str) -> PathBuf{
-        self.folder_path.join(Self::get_hash_string_base64(file_name))
+    //Record a completed download, moving a new file to the path of its variant when the response has a Vary header
+    pub(crate) fn store_variant(&self, int_file_path: &mut PathBuf, file_name: &str, options: &FetchOptions, download: Download) -> Result<(), Box<dyn Error>>{
+        if let Download::Modified(m) = &download {
+            *int_file_path = self.settle_variant(int_file_path, file_name, options, m)?;
+        }
+        self.store_download(int_file_path, file_name, download)
     }
 
-    ///Attempts to retrieve a local file path for a given URL. The method:
-    ///The method handles both cases where the file path check fails entirely (logging an error and attempting to download) 
-    ///and where the file doesn't exist at the expected location (performing a download).
-    /// 
-    /// #Parameters:
-    ///     * url: A string slice containing the URL of the file to retrieve from cache.
-    /// 
-    /// #Returns:
-    ///     * Result<String, Box<dyn Error>>: Returns the full local file path as a string on success, or an error if:
-    ///                                         The file path cannot be retrieved due to invalid Unicode
-    ///                                         File operations fail during download or path checking    
-    pub fn get_local_file_path(&self, url: &str) -> Result<String,Box<dyn Error>> {
-        let int_file_path = self.get_file(url); //self.folder_path.join(Self::get_hash_string_base64(url));
-        let path_check = int_file_path.try_exists();
-        if path_check.is_err() {
-            log_error!("get_local_file_path","Issue finding file '{:?}' trying downloading again",int_file_path);
-            Self::download_file(url, &int_file_path)?;
-        }else{
-            if !path_check.unwrap(){
-                //File not found
-                Self::download_file(url, &int_file_path)?;
-            }
+    //Record a cache hit (metadata and statistics)
+    pub(crate) fn record_hit(&self, int_file_path: &Path, file_name: &str, url: &str){
+        EntryMetadata::touch(int_file_path, file_name, url);
+        self.stats.record_hit(fs::metadata(int_file_path).map(|m| m.len()).unwrap_or(0));
+    }
+
+    //Record a cache hit without blocking the caller on the metadata update
+    #[cfg(feature = "async")]
+    pub(crate) async fn record_hit_async(&self, int_file_path: &Path, file_name: &str, url: &str){
+        let (cache, path, file_name, url) = (self.shared(), int_file_path.to_path_buf(), file_name.to_owned(), url.to_owned());
+        let _ = nonblocking::unblock(move || {
+            cache.record_hit(&path, &file_name, &url);
+            Ok(())
+        }).await;
+    }
+
+    //Instance sharing the folder, settings and statistics of this one. Lets the async functions move folder scans
+    //and metadata updates off the calling task
+    #[cfg(feature = "async")]
+    pub(crate) fn shared(&self) -> Self {
+        Self {
+            folder_path: self.folder_path.clone(),
+            stats: self.stats.clone(),
+            permissions: self.permissions,
+            key_strategy: self.key_strategy.clone(),
+            credentials: self.credentials.clone(),
+            refreshed_auth: self.refreshed_auth.clone(),
+        }
+    }
+
+    //Count failed requests and convert the cached file path to String
+    pub(crate) fn complete_request(&self, result: Result<(), Box<dyn Error>>, int_file_path: &Path) -> Result<String,Box<dyn Error>>{
+        if let Err(e) = result {
+            self.stats.record_error();
+            return Err(e)
         }
         if let Some(full_path) = int_file_path.to_str(){
-            return Ok(full_path.to_owned())
+            Ok(full_path.to_owned())
         }else{
-            return Err(get_error!("get_local_file_path","Unable to retrieve cached file path. Invalid Unicode Path").into())
+            self.stats.record_error();
+            Err(get_error!("get_local_file_path","Unable to retrieve cached file path. Invalid Unicode Path").into())
         }
-    } 
+    }
 
-    ///Encodes the file bytes using standard base64 encoding
-    /// 
-    ///#Parameters
-    ///     * url: A string slice (&str) containing the URL of the file to retrieve
-    /// 
-    ///#Returns
-    ///    Result<String, Box<dyn Error>>: Returns a String containing the base64-encoded file data on success, or an error if:
-    ///    The local file path cannot be determined
-    ///    The file cannot be read from the local cache
-    ///    Base64 encoding fails
-    pub fn get_file_data_base64(&self, url: &str) -> Result<String,Box<dyn Error>> {
-        let full_file_path = self.get_local_file_path(url)?;
-        let file_data_bytes = fs::read(full_file_path)?;
-        Ok(general_purpose::STANDARD.encode(file_data_bytes))
-    }
-
-    ///The invalidate_cache function is responsible for removing a cached file from the local filesystem. 
-    ///This function is typically used to clear stale or outdated cache entries, ensuring that subsequent requests for the specified URL 
-    ///will fetch fresh data rather than using cached content.
-    /// 
-    /// #Parameters
-    /// * url: &str, A string slice representing the URL of the cached resource to be invalidated. This parameter is used to determine which cached file should be removed
-    /// 
-    /// #Returns
-    /// Result<(), Box<dyn Error>>
-    ///     * Success: Ok(()) - Indicates that the cache file was successfully removed
-    ///     * Error: Err(Box<dyn Error>) - Contains a boxed error object describing what went wrong during the cache invalidation process
-    pub fn invalidate_cache(&self, url: &str)-> Result<(),Box<dyn Error>> {
-        let file = self.get_file(url);
-        let exist_check =  file.try_exists();
-        if exist_check.is_ok(){
-            if exist_check.unwrap() {
-                let full_file_path = file.to_str(); 
-                if full_file_path.is_some(){//self.get_local_file_path(url)?;
-                    let _r = remove_file(full_file_path.unwrap())?;
-                }else{
-                    return Err(get_error!("invalidate_cache","Error extractive file path (none)").into())
-                }
-            }else{
-                return Err(get_error!("invalidate_cache","File not found").into())
-            }
-        }else{
-            return Err(get_error!("invalidate_cache","File check Error: {}",exist_check.unwrap_err()).into())
+    //Whether the cached file matches the checksum expected by the options. Entries without a recorded digest do not match
+    pub(crate) fn matches_checksum(int_file_path: &Path, options: &FetchOptions) -> bool{
+        let Some(expected) = &options.checksum else {
+            return true
+        };
+        EntryMetadata::load(int_file_path).is_ok_and(|m| expected.matches(&m))
+    }
+
+    //Validators of a cached file, when the file exists and the server provided an ETag or Last-Modified header
+    pub(crate) fn get_validators(int_file_path: &Path) -> Option<EntryMetadata>{
+        if !int_file_path.exists() {
+            return None
         }
-        Ok(())
+        EntryMetadata::load(int_file_path).ok().filter(|m| m.etag.is_some() || m.last_modified.is_some())
     }
 
-    ///ASYNC The invalidate_cache function is responsible for removing a cached file from the local filesystem. 
-    ///This function is typically used to clear stale or outdated cache entries, ensuring that subsequent requests for the specified URL 
-    ///will fetch fresh data rather than using cached content.
-    /// 
-    /// #Parameters
-    /// * url_name_id: &str, A string slice representing the URL or Name ID of the cached resource to be invalidated. 
-    ///                      This parameter is used to determine which cached file should be removed
-    /// 
-    /// #Returns
-    /// Result<(), Box<dyn Error>>
-    ///     * Success: Ok(()) - Indicates that the cache file was successfully removed
-    ///     * Error: Err(Box<dyn Error>) - Contains a boxed error object describing what went wrong during the cache invalidation process
-    pub async fn invalidate_cache_async(&self, url_name_id: &str)-> Result<(),Box<dyn Error>> {
-        //let full_file_path = self.get_local_file_path_with_name_async(url_name_id,url_name_id).await?;
-        //let _r = remove_file(full_file_path)?;
-        self.invalidate_cache(url_name_id)?;
-        Ok(())
+    //Build file name to standarize it
+    pub(crate) fn get_file(&self, file_name: &str) -> PathBuf{
+        self.folder_path.join(Self::get_hash_string_base64(&self.key_strategy.derive_key(file_name)))
     }
 
-    ///The refresh_cache function is designed to refresh or revalidate a cached resource by first invalidating the existing cache entry 
-    ///and then returning the local file path where the refreshed content is stored.
-    /// 
-    /// #Parameters
-    /// * url: &str, A string slice representing the URL of the cached resource to be invalidated. This parameter is used to determine which cached file should be removed
-    /// 
-    /// #Returns
-    /// Result<(), Box<dyn Error>>:
-    ///     * Success: Ok(String) - Returns the local file path where the refreshed cache content is stored
-    ///     * Error: Err(Box<dyn Error>) - Contains a boxed error object describing what went wrong during the cache refresh process
-    pub fn refresh_cache(&self, url: &str)-> Result<String,Box<dyn Error>> {
-       self.invalidate_cache(url)?;
-       let file_path = self.get_local_file_path(url)?;
-       Ok(file_path)
+    //File name of one variant of an entry (see vary.rs): the key is extended with the values of the request headers it varies on
+    pub(crate) fn get_variant_file(&self, file_name: &str, variant: &str) -> PathBuf{
+        let key = format!("{}\n{}", self.key_strategy.derive_key(file_name), variant);
+        self.folder_path.join(Self::get_hash_string_base64(&key))
+    }
+
+    //Record the outcome of a revalidation (304 keeps the cached file, 200 replaced it)
+    pub(crate) fn store_revalidation(&self, int_file_path: &mut PathBuf, file_name: &str, options: &FetchOptions, download: Download) -> Result<(), Box<dyn Error>>{
+        self.stats.record_revalidation(matches!(download, Download::Modified(_)));
+        self.store_variant(int_file_path, file_name, options, download)
     }
 
-    ///ASYNC The refresh_cache function is designed to refresh or revalidate a cached resource by first invalidating the existing cache entry 
-    ///and then returning the local file path where the refreshed content is stored.
-    /// 
-    /// #Parameters
-    /// * url_name_id: &str, A string slice representing the URL or Name ID of the cached resource to be invalidated. This parameter is used to determine which cached file should be removed
-    /// 
-    /// #Returns
-    /// Result<(), Box<dyn Error>>:
-    ///     * Success: Ok(String) - Returns the local file path where the refreshed cache content is stored
-    ///     * Error: Err(Box<dyn Error>) - Contains a boxed error object describing what went wrong during the cache refresh process
-    pub async fn refresh_cache_async(&self, url_name_id: &str)-> Result<String,Box<dyn Error>> {
-       self.invalidate_cache_async(url_name_id).await?;
-       let file_path = self.get_local_file_path_with_name_token_async(url_name_id,url_name_id, None).await?;
-       Ok(file_path)
-    } 
-
-    ///ASYNC The refresh_cache function is designed to refresh or revalidate a cached resource by first invalidating the existing cache entry 
-    ///and then returning the local file path where the refreshed content is stored.
-    /// 
-    /// #Parameters
-    /// * url: &str, A string slice representing the URL to refresh This parameter is used to determine which cached file should be removed
-    /// * name: name of the file to store
-    /// * token: access token to access the UTL resource
-    /// #Returns
-    /// Result<(), Box<dyn Error>>:
-    ///     * Success: Ok(String) - Returns the local file path where the refreshed cache content is stored
-    ///     * Error: Err(Box<dyn Error>) - Contains a boxed error object describing what went wrong during the cache refresh process
-    pub async fn refresh_cache_with_name_async(&self, url: &str, name: &str, token: Option<&str>)-> Result<String,Box<dyn Error>> {
-       self.invalidate_cache_async(name).await?;
-       let file_path = self.get_local_file_path_with_name_token_async(url,name, token).await?;
-       Ok(file_path)
-    }       
-
-    ///ASYNC The refresh_cache function is designed to refresh or revalidate a cached resource by first invalidating the existing cache entry 
-    ///and then returning the local file path where the refreshed content is stored.
-    /// 
-    /// #Parameters
-    /// * url: &str, A string slice representing the URL to refresh This parameter is used to determine which cached file should be removed
-    /// * token: access token to access the UTL resource
-    /// 
-    /// #Returns
-    /// Result<(), Box<dyn Error>>:
-    ///     * Success: Ok(String) - Returns the local file path where the refreshed cache content is stored
-    ///     * Error: Err(Box<dyn Error>) - Contains a boxed error object describing what went wrong during the cache refresh process
-    pub async fn refresh_cache_with_token_async(&self, url: &str, token: Option<&str>)-> Result<String,Box<dyn Error>> {
-       self.invalidate_cache_async(url).await?;
-       let file_path = self.get_local_file_path_with_name_token_async(url,url, token).await?;
-       Ok(file_path)
-    }      
 }
 
 //************** */
@@ -405,10 +424,13 @@ mod bt_cache_tests {
     use std::sync::Once;
 
     use bt_logger::{LogLevel, LogTarget, build_logger, log_verbose};
+    #[cfg(feature = "blocking")]
     use regex::Regex;
 
     use super::*;
+    use crate::test_support::TempDir;
 
+    #[cfg(feature = "blocking")]
     const FILE_URL: &str = "https://avatars.githubusercontent.com/u/188628667?v=4";
     //const FILE_URL: &str = "https://www.google.com/s2/favicons?sz=64&domain=indeed.com";
     const APP_NAME: &str = "bt_cache";    
@@ -420,6 +442,7 @@ mod bt_cache_tests {
         });
     }
 
+    #[cfg(feature = "blocking")]
     #[test]
     fn test_get_file_path_success() {
         let local_cache = BTCache::new(Some(APP_NAME)).unwrap();
@@ -428,6 +451,7 @@ mod bt_cache_tests {
         assert!(re.is_match(&p));
     }
 
+    #[cfg(feature = "blocking")]
     #[test]
     fn test_get_file_data_success() {
         ini_log();
@@ -437,6 +461,7 @@ mod bt_cache_tests {
         assert!(p.is_ok());
     }    
 
+    #[cfg(feature = "blocking")]
     #[test]
     fn test_get_file_data_fail() {
         let local_cache = BTCache::new(Some(APP_NAME)).unwrap();
@@ -445,6 +470,7 @@ mod bt_cache_tests {
         assert!(p.is_err());
     }    
 
+    #[cfg(feature = "blocking")]
     #[test]
     fn test_invaldiate_success() {
         let local_cache = BTCache::new(Some(APP_NAME)).unwrap();
@@ -462,6 +488,7 @@ mod bt_cache_tests {
         assert!(r.is_err())
     }    
     
+    #[cfg(feature = "blocking")]
     #[test]
     fn test_refresh_success() {
         ini_log();
@@ -471,9 +498,110 @@ mod bt_cache_tests {
         log_verbose!("test_refresh_success","Result {:?}",r);
         assert!(r.is_ok())
     }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_with_dir() {
+        let server = crate::test_server::TestServer::start(|_| crate::test_server::TestResponse::ok(b"custom dir"));
+        let dir = TempDir::new("with_dir");
+        let local_cache = BTCache::with_dir(dir.path()).unwrap();
+        let p = local_cache.get_local_file_path(&server.url("/with_dir")).unwrap();
+        assert_eq!(local_cache.folder_path(), dir.path());
+        assert!(Path::new(&p).starts_with(dir.path()));
+        assert_eq!(fs::read(&p).unwrap(), b"custom dir");
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_migrate_from() {
+        let server = crate::test_server::TestServer::start(|req| match req.header("Accept-Language") {
+            Some(lang) => crate::test_server::TestResponse::ok(lang.as_bytes()).header("Vary", "Accept-Language"),
+            None => crate::test_server::TestResponse::ok(b"migrated"),
+        });
+        let base = TempDir::new("migrate");
+        let old_dir = base.join("old");
+        let new_dir = base.join("new");
+        let (url, localized) = (server.url("/migrate"), server.url("/localized"));
+        let (en, fr) = (FetchOptions::new().with_header("Accept-Language", "en"), FetchOptions::new().with_header("Accept-Language", "fr"));
+        let old_cache = BTCache::with_dir(&old_dir).unwrap();
+        old_cache.get_local_file_path(&url).unwrap();
+        old_cache.get_local_file_path_with_options(&localized, &localized, &en).unwrap();
+        old_cache.get_local_file_path_with_options(&localized, &localized, &fr).unwrap();
+        fs::write(old_dir.join("unrelated.txt"), b"keep").unwrap();
+
+        let local_cache = BTCache::with_dir(&new_dir).unwrap();
+        assert_eq!(local_cache.migrate_from(&old_dir).unwrap(), MigrationReport { migrated: 3, skipped: 0 });
+
+        let info = local_cache.entry_info(&url).unwrap().unwrap();
+        assert_eq!(info.url.as_deref(), Some(url.as_str()));
+        assert_eq!(fs::read(local_cache.get_local_file_path(&url).unwrap()).unwrap(), b"migrated");
+        //The variants are still selected by the request headers
+        assert_eq!(fs::read(local_cache.get_local_file_path_with_options(&localized, &localized, &fr).unwrap()).unwrap(), b"fr");
+        assert_eq!(fs::read(local_cache.get_local_file_path_with_options(&localized, &localized, &en).unwrap()).unwrap(), b"en");
+        assert_eq!(server.request_count(), 3);
+        assert_eq!(fs::read_dir(&old_dir).unwrap().count(), 1);
+        assert!(old_dir.join("unrelated.txt").exists());
+
+        //Entries already cached are kept, the old copies are discarded and counted
+        old_cache.get_local_file_path(&url).unwrap();
+        old_cache.get_local_file_path(&server.url("/migrate-new")).unwrap();
+        assert_eq!(local_cache.migrate_from(&old_dir).unwrap(), MigrationReport { migrated: 1, skipped: 1 });
+        assert_eq!(fs::read_dir(&old_dir).unwrap().count(), 1);
+
+        //Same folder under another spelling: nothing is moved or removed
+        let same = new_dir.join("..").join("new");
+        assert_eq!(local_cache.migrate_from(&same).unwrap(), MigrationReport::default());
+        assert_eq!(fs::read(local_cache.get_local_file_path(&url).unwrap()).unwrap(), b"migrated");
+    }
+
+    #[test]
+    fn test_dir_override() {
+        assert_eq!(BTCache::dir_override(None), None);
+        assert_eq!(BTCache::dir_override(Some(String::new())), None);
+        assert_eq!(BTCache::dir_override(Some("/mnt/cache".to_owned())), Some(PathBuf::from("/mnt/cache")));
+    }
+
+    #[test]
+    fn test_new_with_resolver() {
+        let base = TempDir::new("new_resolver");
+        let resolver = DirResolver::new().with_base(DirKind::Data, base.join("data")).with_base(DirKind::Cache, base.join("cache"));
+
+        let data_cache = BTCache::new_with_resolver(Some(APP_NAME), CacheLocation::Data, &resolver).unwrap();
+        assert_eq!(data_cache.folder_path(), base.join("data").join(APP_NAME).join("cache"));
+        fs::write(data_cache.get_file("old entry"), b"old").unwrap();
+
+        let cache = BTCache::new_with_resolver(Some(APP_NAME), CacheLocation::Cache, &resolver).unwrap();
+        assert_eq!(cache.folder_path(), base.join("cache").join(APP_NAME));
+        assert_eq!(fs::read(cache.get_file("old entry")).unwrap(), b"old");
+        assert!(!data_cache.folder_path().exists());
+
+        let dir = base.join("override");
+        let env_dir = dir.to_string_lossy().into_owned();
+        let env_resolver = DirResolver::with_env(move |name| (name == CACHE_DIR_ENV).then(|| env_dir.clone()));
+        assert_eq!(BTCache::new_with_resolver(Some(APP_NAME), CacheLocation::Cache, &env_resolver).unwrap().folder_path(), dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_new_with_resolver_same_folder() {
+        //The cache location is a link to the data location: the entries must stay where they are
+        let base = TempDir::new("same_folder");
+        let data_folder = base.join("data").join(APP_NAME).join("cache");
+        fs::create_dir_all(&data_folder).unwrap();
+        fs::create_dir_all(base.join("cache")).unwrap();
+        std::os::unix::fs::symlink(&data_folder, base.join("cache").join(APP_NAME)).unwrap();
+        let resolver = DirResolver::new().with_base(DirKind::Data, base.join("data")).with_base(DirKind::Cache, base.join("cache"));
+        let data_cache = BTCache::new_with_resolver(Some(APP_NAME), CacheLocation::Data, &resolver).unwrap();
+        fs::write(data_cache.get_file("entry"), b"kept").unwrap();
+
+        let cache = BTCache::new_with_resolver(Some(APP_NAME), CacheLocation::Cache, &resolver).unwrap();
+
+        assert_eq!(fs::read(cache.get_file("entry")).unwrap(), b"kept");
+        assert_eq!(fs::read(data_folder.join(data_cache.get_file("entry").file_name().unwrap())).unwrap(), b"kept");
+    }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "async"))]
 mod bt_cache_with_name_async_tests {
     use std::sync::Once;
 
@@ -538,7 +666,7 @@ mod bt_cache_with_name_async_tests {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "async"))]
 mod bt_cache_async_tests {
     use std::sync::Once;
 
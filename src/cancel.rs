@@ -0,0 +1,202 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll, Waker},
+};
+
+///CancellationToken is used to cancel fetches started with FetchOptions::with_cancellation.
+///The token can be cloned and shared between threads/tasks; cancelling any clone cancels all of them.
+///
+///Async fetches stop immediately when the token is cancelled. Sync fetches stop before the next chunk of data is written.
+///In both cases the partially downloaded file is removed so the cache never contains a partial entry.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<TokenState>,
+}
+
+#[derive(Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl CancellationToken {
+    ///Constructor. The token starts as not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Cancel every fetch using this token (or any of its clones)
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.inner.wakers.lock().unwrap_or_else(|e| e.into_inner()));
+        for w in wakers {
+            w.wake();
+        }
+    }
+
+    ///Returns true once cancel has been called
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    ///Future that completes when the token is cancelled
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled { token: self.clone() }
+    }
+}
+
+///Future returned by CancellationToken::cancelled
+pub struct Cancelled {
+    token: CancellationToken,
+}
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        let mut wakers = self.token.inner.wakers.lock().unwrap_or_else(|e| e.into_inner());
+        //Check again while holding the lock so a concurrent cancel is not missed
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod cancel_tests {
+    use std::{fs, path::Path, thread, time::Duration};
+
+    use super::*;
+    use crate::{
+        cache::BTCache,
+        options::FetchOptions,
+        test_server::{TestResponse, TestServer},
+        test_support::TempCache,
+    };
+
+    const BODY: &[u8] = &[1u8; 8192];
+
+    //True when neither the cache file nor any partial download exists for the entry
+    fn no_entry_left(local_cache: &BTCache, name: &str) -> bool {
+        let entry = local_cache.get_file(name);
+        let prefix = entry.file_name().unwrap().to_string_lossy().into_owned();
+        let folder = entry.parent().unwrap();
+        !entry.exists() && !leftovers(folder, &prefix)
+    }
+
+    fn leftovers(folder: &Path, prefix: &str) -> bool {
+        fs::read_dir(folder).unwrap().flatten().any(|e| e.file_name().to_string_lossy().starts_with(prefix))
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cancel_async_leaves_no_entry() {
+        let server = TestServer::start(|_| TestResponse::ok(BODY).slow(512, Duration::from_millis(50)));
+        let local_cache = TempCache::new("cancel_async");
+        let token = CancellationToken::new();
+        let options = FetchOptions::new().with_cancellation(token.clone());
+        let url = server.url("/cancel_async");
+
+        let th_token = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            th_token.cancel();
+        });
+        let r = local_cache.get_local_file_path_with_options_async(&url, &url, &options).await;
+
+        assert!(r.is_err());
+        assert!(token.is_cancelled());
+        assert!(no_entry_left(&local_cache, &url));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_cancel_sync_leaves_no_entry() {
+        let server = TestServer::start(|_| TestResponse::ok(BODY).slow(512, Duration::from_millis(50)));
+        let local_cache = TempCache::new("cancel_sync");
+        let token = CancellationToken::new();
+        let options = FetchOptions::new().with_cancellation(token.clone());
+        let url = server.url("/cancel_sync");
+
+        let th_token = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            th_token.cancel();
+        });
+        let r = local_cache.get_local_file_path_with_options(&url, &url, &options);
+
+        assert!(r.is_err());
+        assert!(no_entry_left(&local_cache, &url));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_already_cancelled_does_not_download() {
+        let server = TestServer::start(|_| TestResponse::ok(BODY));
+        let local_cache = TempCache::new("cancel_already");
+        let token = CancellationToken::new();
+        token.cancel();
+        let url = server.url("/cancel_before");
+
+        let r = local_cache.get_local_file_path_with_options_async(&url, &url, &FetchOptions::new().with_cancellation(token)).await;
+
+        assert!(r.is_err());
+        assert_eq!(server.request_count(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_timeout_async_leaves_no_entry() {
+        let server = TestServer::start(|_| TestResponse::ok(BODY).slow(512, Duration::from_millis(50)));
+        let local_cache = TempCache::new("cancel_timeout_async");
+        let options = FetchOptions::new().with_timeout(Duration::from_millis(200));
+        let url = server.url("/timeout_async");
+
+        let r = local_cache.get_local_file_path_with_options_async(&url, &url, &options).await;
+
+        assert!(r.is_err());
+        assert!(no_entry_left(&local_cache, &url));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_timeout_sync_leaves_no_entry() {
+        let server = TestServer::start(|_| TestResponse::ok(BODY).slow(512, Duration::from_millis(50)));
+        let local_cache = TempCache::new("cancel_timeout_sync");
+        let options = FetchOptions::new().with_timeout(Duration::from_millis(200));
+        let url = server.url("/timeout_sync");
+
+        let r = local_cache.get_local_file_path_with_options(&url, &url, &options);
+
+        assert!(r.is_err());
+        assert!(no_entry_left(&local_cache, &url));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_long_timeout_succeeds() {
+        let server = TestServer::start(|_| TestResponse::ok(BODY));
+        let local_cache = TempCache::new("cancel_long_timeout");
+        let options = FetchOptions::new().with_timeout(Duration::from_secs(30));
+        let url = server.url("/timeout_ok");
+
+        let p = local_cache.get_local_file_path_with_options_async(&url, &url, &options).await.unwrap();
+
+        assert_eq!(fs::read(p).unwrap(), BODY);
+    }
+}
@@ -0,0 +1,182 @@
+use std::{error::Error, io::{self, Read}};
+
+use bt_logger::get_error;
+use sha2::{Sha256, Sha512};
+use sha3::{Digest, Sha3_256};
+
+use crate::metadata::{EntryMetadata, hex_digest};
+
+//Algorithms of the checksums accepted by FetchOptions::with_checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    Sha3_256,
+    Sha256,
+    Sha512,
+}
+
+//Tags of the algorithms ("<tag>:<hex>")
+const TAGS: [(&str, Algorithm); 3] = [("sha3-256", Algorithm::Sha3_256), ("sha256", Algorithm::Sha256), ("sha512", Algorithm::Sha512)];
+
+//Expected checksum of the content (FetchOptions::with_checksum): algorithm and lowercase hex encoded digest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Checksum {
+    algorithm: Algorithm,
+    hex: String,
+}
+
+impl Checksum {
+    //Parse "sha256:<hex>", "sha512:<hex>", "sha3-256:<hex>" or an untagged hex digest (SHA3-256). Tags are case insensitive
+    pub(crate) fn parse(checksum: &str) -> Self {
+        let checksum = checksum.trim().to_ascii_lowercase();
+        for (tag, algorithm) in TAGS {
+            if let Some(hex) = checksum.strip_prefix(tag).and_then(|c| c.strip_prefix(':')) {
+                return Self { algorithm, hex: hex.to_owned() }
+            }
+        }
+        Self { algorithm: Algorithm::Sha3_256, hex: checksum }
+    }
+
+    //Whether a cached entry matches. SHA3-256 checksums are compared with the digest of the entry, the other algorithms with
+    //the checksum verified when the entry was fetched (entries fetched without it do not match and are fetched again)
+    pub(crate) fn matches(&self, metadata: &EntryMetadata) -> bool {
+        match self.algorithm {
+            Algorithm::Sha3_256 => metadata.digest.as_deref().is_some_and(|d| d.eq_ignore_ascii_case(&self.hex)),
+            _ => metadata.checksum.as_deref().is_some_and(|c| c.eq_ignore_ascii_case(&self.tagged())),
+        }
+    }
+
+    //"<tag>:<hex>" form recorded in the entry metadata
+    fn tagged(&self) -> String {
+        let tag = TAGS.iter().find(|(_, a)| *a == self.algorithm).map(|(t, _)| *t).unwrap_or_default();
+        format!("{}:{}", tag, self.hex)
+    }
+}
+
+//Hasher of the expected checksum, when its algorithm is not the SHA3-256 computed for every entry
+enum ChecksumHasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+//Hashes fetched content: SHA3-256 for the entry metadata, plus the algorithm of the expected checksum when it is another one
+pub(crate) struct ContentHasher {
+    sha3: Sha3_256,
+    expected: Option<(Checksum, Option<ChecksumHasher>)>,
+}
+
+impl ContentHasher {
+    pub(crate) fn new(expected: Option<&Checksum>) -> Self {
+        let expected = expected.map(|c| {
+            let hasher = match c.algorithm {
+                Algorithm::Sha3_256 => None,
+                Algorithm::Sha256 => Some(ChecksumHasher::Sha256(Sha256::new())),
+                Algorithm::Sha512 => Some(ChecksumHasher::Sha512(Sha512::new())),
+            };
+            (c.clone(), hasher)
+        });
+        Self { sha3: Sha3_256::new(), expected }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.sha3.update(data);
+        match &mut self.expected {
+            Some((_, Some(ChecksumHasher::Sha256(h)))) => h.update(data),
+            Some((_, Some(ChecksumHasher::Sha512(h)))) => h.update(data),
+            _ => {},
+        }
+    }
+
+    //Hash the whole content of reader. Returns its size
+    pub(crate) fn read_from(&mut self, mut reader: impl Read) -> io::Result<u64> {
+        let mut buffer = [0u8; 64 * 1024];
+        let mut size = 0u64;
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                return Ok(size)
+            }
+            self.update(&buffer[..read]);
+            size += read as u64;
+        }
+    }
+
+    //Digests of the content, once checked against the expected checksum. Fails on a mismatch (the caller removes the temporary file)
+    pub(crate) fn finish(self, url: &str) -> Result<ContentDigest, Box<dyn Error>> {
+        let digest = hex_digest(self.sha3);
+        let Some((expected, hasher)) = self.expected else {
+            return Ok(ContentDigest { digest, checksum: None })
+        };
+        let actual = match hasher {
+            None => digest.clone(),
+            Some(ChecksumHasher::Sha256(h)) => to_hex(&h.finalize()),
+            Some(ChecksumHasher::Sha512(h)) => to_hex(&h.finalize()),
+        };
+        if actual != expected.hex {
+            return Err(get_error!("verify_checksum","Checksum mismatch for '{}': expected {}, got {}", url, expected.tagged(), actual).into())
+        }
+        let checksum = (expected.algorithm != Algorithm::Sha3_256).then(|| expected.tagged());
+        Ok(ContentDigest { digest, checksum })
+    }
+}
+
+//Digests of fetched content recorded in the entry metadata
+pub(crate) struct ContentDigest {
+    //Hex encoded SHA3-256 digest
+    pub digest: String,
+    //Checksum verified with another algorithm ("sha256:<hex>", "sha512:<hex>")
+    pub checksum: Option<String>,
+}
+
+impl ContentDigest {
+    pub(crate) fn record(self, metadata: &mut EntryMetadata) {
+        metadata.digest = Some(self.digest);
+        metadata.checksum = self.checksum;
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod checksum_tests {
+    use super::*;
+
+    const SHA256_ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const SHA512_ABC: &str = "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f";
+    const SHA3_256_ABC: &str = "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532";
+
+    fn hash(checksum: &str, data: &[u8]) -> Result<ContentDigest, Box<dyn Error>> {
+        let mut hasher = ContentHasher::new(Some(&Checksum::parse(checksum)));
+        hasher.update(data);
+        hasher.finish("test")
+    }
+
+    #[test]
+    fn test_algorithms() {
+        let d = hash(SHA3_256_ABC, b"abc").unwrap();
+        assert_eq!((d.digest.as_str(), d.checksum), (SHA3_256_ABC, None));
+        let d = hash(&format!("SHA256:{}", SHA256_ABC.to_uppercase()), b"abc").unwrap();
+        assert_eq!((d.digest.as_str(), d.checksum), (SHA3_256_ABC, Some(format!("sha256:{}", SHA256_ABC))));
+        let d = hash(&format!("sha512:{}", SHA512_ABC), b"abc").unwrap();
+        assert_eq!(d.checksum, Some(format!("sha512:{}", SHA512_ABC)));
+        assert!(hash(&format!("sha3-256:{}", SHA3_256_ABC), b"abc").is_ok());
+
+        assert!(hash(&format!("sha256:{}", SHA256_ABC), b"abd").is_err());
+        assert!(hash(SHA256_ABC, b"abc").is_err());
+        assert!(hash(&format!("md5:{}", SHA256_ABC), b"abc").is_err());
+    }
+
+    #[test]
+    fn test_matches_entry() {
+        let mut metadata = EntryMetadata::default();
+        hash(&format!("sha256:{}", SHA256_ABC), b"abc").unwrap().record(&mut metadata);
+        assert!(Checksum::parse(&format!("sha256:{}", SHA256_ABC)).matches(&metadata));
+        assert!(Checksum::parse(SHA3_256_ABC).matches(&metadata));
+        assert!(Checksum::parse(&format!("sha3-256:{}", SHA3_256_ABC)).matches(&metadata));
+        assert!(!Checksum::parse(&format!("sha512:{}", SHA512_ABC)).matches(&metadata));
+    }
+}
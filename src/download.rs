@@ -0,0 +1,580 @@
+//Downloads: HTTP clients, temporary files and the request/response handling shared by every fetch.
+//The async functions use the async HTTP client with the "tokio" feature. Without it (runtime agnostic "async" build) the
+//blocking client runs on a background thread, and a blocking only build (no "async" feature) has no async code at all.
+//The blocking client and the sync download are only compiled with the "blocking" feature.
+//file:// and data: URLs are not downloaded but stored by ingest_local, with any feature set, when FetchOptions::with_local_sources allows them.
+use std::{env, error::Error, fs, io::Read, path::{Path, PathBuf}, time::Duration};
+#[cfg(feature = "async")]
+use std::pin::pin;
+#[cfg(all(feature = "async", not(feature = "tokio")))]
+use std::thread;
+
+use bt_logger::{get_error, log_verbose};
+#[cfg(all(feature = "async", not(feature = "tokio")))]
+use futures_channel::oneshot;
+#[cfg(feature = "async")]
+use futures_util::future::{Either, select};
+use once_cell::sync;
+use reqwest::{Method, StatusCode, Url, header::{CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH}};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+#[cfg(all(feature = "async", not(feature = "tokio")))]
+use crate::cancel::CancellationToken;
+#[cfg(feature = "tokio")]
+use crate::fs_io::nonblocking;
+use crate::{auth::{Auth, CredentialProvider, RefreshedCredentials, ResolvedAuth}, cache::temp_path, checksum::{ContentDigest, ContentHasher}, fs_io::blocking, metadata::EntryMetadata, options::FetchOptions, permissions::{self, PermissionPolicy}, progress::ProgressTracker, source::LocalSource};
+
+static DEFAULT_USER_AGENT: sync::Lazy<String> = sync::Lazy::new(||{
+    format!("Mozilla/5.0 ({}; {}; {}) {}/{}", env::consts::FAMILY, env::consts::OS, env::consts::ARCH, option_env!("CARGO_PKG_NAME").unwrap_or("bt_file_cache"), option_env!("CARGO_PKG_VERSION").unwrap_or("0.0.1b"))
+});
+
+#[cfg(feature = "tokio")]
+static HTTP_CLIENT: sync::Lazy<reqwest::Client> = sync::Lazy::new(||{ 
+        const CLIENT_REQUEST_TIMEOUT: u64 = 10;
+        if let Ok(c) = reqwest::Client::builder()
+                                        .timeout(Duration::from_secs(CLIENT_REQUEST_TIMEOUT))
+                                        .user_agent(DEFAULT_USER_AGENT.clone())
+                                        .build(){
+            c
+        }else{
+            reqwest::Client::new()
+        }
+    });
+
+#[cfg(feature = "blocking")]
+static HTTP_BLOCKING_CLIENT: sync::Lazy<reqwest::blocking::Client> = sync::Lazy::new(||{ 
+        const CLIENT_REQUEST_TIMEOUT: u64 = 10;
+        if let Ok(c) = reqwest::blocking::Client::builder()
+                                        .timeout(Duration::from_secs(CLIENT_REQUEST_TIMEOUT))
+                                        .user_agent(DEFAULT_USER_AGENT.clone())
+                                        .build(){
+            c
+        }else{
+            reqwest::blocking::Client::new()
+        }
+    });
+
+//Temporary file receiving a download. The file is removed when dropped unless it was committed,
+//so failed, cancelled or dropped downloads never leave partial data behind.
+pub(crate) struct PartFile {
+    path: PathBuf,
+    committed: bool,
+}
+
+impl PartFile {
+    //Temporary file name for a download of int_file_path. The caller creates the file at self.path
+    fn new(int_file_path: &Path) -> Self {
+        Self { path: temp_path(int_file_path, "part"), committed: false }
+    }
+
+    //Move the completed download into place
+    pub(crate) fn commit(mut self, int_file_path: &Path) -> Result<(), Box<dyn Error>> {
+        blocking::rename(&self.path, int_file_path)?;
+        self.committed = true;
+        Ok(())
+    }
+
+    //ASYNC Move the completed download into place
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn commit_async(mut self, int_file_path: &Path) -> Result<(), Box<dyn Error>> {
+        nonblocking::rename(&self.path, int_file_path).await?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = blocking::remove_file(&self.path);
+        }
+    }
+}
+
+//Outcome of a download. NotModified is only possible for conditional requests (revalidation)
+pub(crate) enum Download {
+    Modified(EntryMetadata),
+    NotModified(EntryMetadata),
+}
+
+//Settings of a BTCache used by its downloads. Owned (cheap to clone) so a download can run on another thread
+#[derive(Clone)]
+pub(crate) struct Downloader {
+    pub(crate) permissions: PermissionPolicy,
+    pub(crate) credentials: Option<CredentialProvider>,
+    pub(crate) refreshed_auth: RefreshedCredentials,
+}
+
+//Cancels the token when dropped
+#[cfg(all(feature = "async", not(feature = "tokio")))]
+struct AbortOnDrop(CancellationToken);
+
+#[cfg(all(feature = "async", not(feature = "tokio")))]
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+impl Downloader {
+    ///ASYNC Helper Method. Downloads a file from the specified URL and saves it to the given file path.
+    ///Uses reqwest for HTTP requests and streams the response body into a temporary file that is renamed once complete,
+    ///reporting the progress to the observer registered in the options.
+    ///The download stops as soon as the cancellation token in the options is cancelled.
+    /// 
+    ///#Parameters
+    /// * url: A string slice containing the URL to download.
+    /// * int_file_path: A reference to a PathBuf specifying where the downloaded file should be saved.
+    /// * options: Fetch options (access token, progress observer, timeout/deadline, cancellation)
+    /// * validators: Metadata of the cached file when revalidating. Makes the request conditional (If-None-Match/If-Modified-Since)
+    ///
+    ///#Returns
+    /// *   Result<Download, Box<dyn Error>>: Returns the response metadata on successful download (or 304 Not Modified), or an error if the download or file creation fails.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn download_file_async(&self, url: &str, int_file_path: &Path, options: &FetchOptions, validators: Option<&EntryMetadata>) -> Result<Download, Box<dyn Error>>{
+        //Local sources are read on the blocking pool
+        if let Some(source) = LocalSource::parse_allowed(url, options.local_sources)? {
+            let (downloader, url, path, options) = (self.clone(), url.to_owned(), int_file_path.to_path_buf(), options.clone());
+            let ingest = move || downloader.ingest_local(&url, source, &path, &options).map_err(|e| e.to_string());
+            return tokio::task::spawn_blocking(ingest).await?.map_err(|e| e.into())
+        }
+        let Some(token) = &options.cancel else {
+            return self.transfer_file_async(url, int_file_path, options, validators).await
+        };
+
+        //Dropping the transfer future removes its temporary file
+        let transfer = pin!(self.transfer_file_async(url, int_file_path, options, validators));
+        let cancelled = pin!(token.cancelled());
+        match select(transfer, cancelled).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(get_error!("download_file_async","Download cancelled: {}", url).into()),
+        }
+    }
+
+    //ASYNC Send a request with the given credentials
+    #[cfg(feature = "tokio")]
+    async fn send_request_async(&self, url: &str, options: &FetchOptions, auth: Option<&Auth>, validators: Option<&EntryMetadata>) -> Result<reqwest::Response, Box<dyn Error>>{
+        let plan = RequestPlan::new(url, options, auth, validators)?;
+        let mut request_builder = HTTP_CLIENT.request(plan.method, plan.url).headers(plan.headers);
+        if let Some(body) = plan.body{
+            request_builder = request_builder.body(body);
+        }
+        if let Some(timeout) = plan.timeout{
+            request_builder = request_builder.timeout(timeout);
+        }
+        Ok(request_builder.send().await?)
+    }
+
+    //ASYNC Send the request and stream the response body into the cache
+    #[cfg(feature = "tokio")]
+    async fn transfer_file_async(&self, url: &str, int_file_path: &Path, options: &FetchOptions, validators: Option<&EntryMetadata>) -> Result<Download, Box<dyn Error>>{
+        let auth = self.refreshed_auth.get(url, options.auth.as_ref()).or_else(|| options.auth.clone());
+        let mut authenticated = auth.is_some();
+        let mut response = self.send_request_async(url, options, auth.as_ref(), validators).await?;
+        if let Some(auth) = self.refreshed_credentials_async(url, options, response.status()).await?{
+            authenticated = true;
+            response = self.send_request_async(url, options, Some(&auth), validators).await?;
+        }
+        if validators.is_some() && response.status() == StatusCode::NOT_MODIFIED{
+            return Ok(Download::NotModified(EntryMetadata::from_response("", url, 0, response.headers())))
+        }
+        if response.status().is_client_error() || response.status().is_server_error(){
+            return Err(get_error!("download_file_async","Request Error: {}", response.status()).into())
+        }
+
+        let mut progress = ProgressTracker::new(response.content_length(), options.progress.clone());
+        let part_file = PartFile::new(int_file_path);
+        let mut file = nonblocking::FileWriter::create(&part_file.path, self.permissions.file_mode_for(authenticated)).await?;
+        let mut hasher = ContentHasher::new(options.checksum.as_ref());
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+            hasher.update(&chunk);
+            progress.advance(chunk.len());
+        }
+        file.finish().await?;
+        let digest = hasher.finish(url)?;
+
+        part_file.commit_async(int_file_path).await?;
+        let mut metadata = EntryMetadata::from_response("", url, progress.downloaded(), response.headers());
+        digest.record(&mut metadata);
+        Ok(Download::Modified(metadata))
+    }
+
+    ///ASYNC Helper Method. Downloads a file from the specified URL and saves it to the given file path.
+    ///Without the "tokio" feature the download runs with the blocking HTTP client on a background thread and the task only waits
+    ///for its result, so the async functions work with any executor (async-std, smol, ...).
+    ///The download stops as soon as the cancellation token in the options is cancelled or the returned future is dropped.
+    /// 
+    ///#Parameters
+    /// * url: A string slice containing the URL to download.
+    /// * int_file_path: A reference to a PathBuf specifying where the downloaded file should be saved.
+    /// * options: Fetch options (access token, progress observer, timeout/deadline, cancellation)
+    /// * validators: Metadata of the cached file when revalidating. Makes the request conditional (If-None-Match/If-Modified-Since)
+    ///
+    ///#Returns
+    /// *   Result<Download, Box<dyn Error>>: Returns the response metadata on successful download (or 304 Not Modified), or an error if the download or file creation fails.
+    #[cfg(all(feature = "async", not(feature = "tokio")))]
+    pub(crate) async fn download_file_async(&self, url: &str, int_file_path: &Path, options: &FetchOptions, validators: Option<&EntryMetadata>) -> Result<Download, Box<dyn Error>>{
+        if options.is_cancelled() {
+            return Err(get_error!("download_file_async","Download cancelled: {}", url).into())
+        }
+        //Cancelled when the fetch is cancelled or this future is dropped: the thread stops before the next chunk and removes its temporary file
+        let abort = AbortOnDrop(CancellationToken::new());
+        let thread_options = FetchOptions { cancel: Some(abort.0.clone()), ..options.clone() };
+        let (downloader, thread_url, path, validators) = (self.clone(), url.to_owned(), int_file_path.to_path_buf(), validators.cloned());
+        let (sender, receiver) = oneshot::channel();
+        thread::Builder::new().name("bt_file_cache-download".to_owned()).spawn(move || {
+            let result = downloader.download_file(&thread_url, &path, &thread_options, validators.as_ref()).map_err(|e| e.to_string());
+            let _ = sender.send(result);
+        })?;
+
+        let received = match &options.cancel {
+            Some(token) => match select(receiver, pin!(token.cancelled())).await {
+                Either::Left((r, _)) => r,
+                Either::Right((_, receiver)) => {
+                    //Wait for the thread to stop and remove its temporary file
+                    abort.0.cancel();
+                    let _ = receiver.await;
+                    return Err(get_error!("download_file_async","Download cancelled: {}", url).into())
+                },
+            },
+            None => receiver.await,
+        };
+        match received {
+            Ok(result) => result.map_err(|e| e.into()),
+            Err(_) => Err(get_error!("download_file_async","Download of '{}' ended without a result", url).into()),
+        }
+    }
+
+    ///Helper Method. Downloads a file from the specified URL and saves it to the given file path.
+    ///Uses reqwest for HTTP requests and streams the response body into a temporary file that is renamed once complete,
+    ///reporting the progress to the observer registered in the options.
+    /// 
+    ///#Parameters
+    /// * url: A string slice containing the URL to download.
+    /// * int_file_path: A reference to a PathBuf specifying where the downloaded file should be saved.
+    /// * options: Fetch options (access token, progress observer, timeout/deadline, cancellation)
+    /// * validators: Metadata of the cached file when revalidating. Makes the request conditional (If-None-Match/If-Modified-Since)
+    ///
+    ///#Returns
+    /// *   Result<Download, Box<dyn Error>>: Returns the response metadata on successful download (or 304 Not Modified), or an error if the download or file creation fails.
+    #[cfg(feature = "blocking")]
+    pub(crate) fn download_file(&self, url: &str, int_file_path: &Path, options: &FetchOptions, validators: Option<&EntryMetadata>) -> Result<Download, Box<dyn Error>>{
+        if let Some(source) = LocalSource::parse_allowed(url, options.local_sources)? {
+            return self.ingest_local(url, source, int_file_path, options)
+        }
+        let auth = self.refreshed_auth.get(url, options.auth.as_ref()).or_else(|| options.auth.clone());
+        let mut authenticated = auth.is_some();
+        let mut download_response = self.send_request(url, options, auth.as_ref(), validators)?;
+        if let Some(auth) = self.refreshed_credentials(url, options, download_response.status())?{
+            authenticated = true;
+            download_response = self.send_request(url, options, Some(&auth), validators)?;
+        }
+        if validators.is_some() && download_response.status() == StatusCode::NOT_MODIFIED{
+            return Ok(Download::NotModified(EntryMetadata::from_response("", url, 0, download_response.headers())))
+        }
+
+        if download_response.status().is_client_error() || download_response.status().is_server_error(){
+            return Err(get_error!("download_file","Request Error: {}", download_response.status()).into())
+        }
+
+        let content_length = download_response.content_length();
+        let part_file = PartFile::new(int_file_path);
+        let (size, digest) = self.write_part(url, &mut download_response, content_length, &part_file, options, authenticated)?;
+
+        part_file.commit(int_file_path)?;
+        let mut metadata = EntryMetadata::from_response("", url, size, download_response.headers());
+        digest.record(&mut metadata);
+        Ok(Download::Modified(metadata))
+    }
+
+    //Stream reader into the temporary file, reporting the progress and stopping when the fetch is cancelled.
+    //Returns the size and digests of the content, once checked against the checksum expected by the options
+    fn write_part(&self, url: &str, mut reader: impl Read, length: Option<u64>, part_file: &PartFile, options: &FetchOptions, authenticated: bool) -> Result<(u64, ContentDigest), Box<dyn Error>>{
+        let mut progress = ProgressTracker::new(length, options.progress.clone());
+        let mut file = blocking::FileWriter::create(&part_file.path, self.permissions.file_mode_for(authenticated))?;
+        let mut hasher = ContentHasher::new(options.checksum.as_ref());
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            if options.is_cancelled() {
+                return Err(get_error!("download_file","Download cancelled: {}", url).into())
+            }
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])?;
+            hasher.update(&buffer[..read]);
+            progress.advance(read);
+        }
+        file.finish()?;
+        Ok((progress.downloaded(), hasher.finish(url)?))
+    }
+
+    //Write the content of reader to a temporary file for int_file_path (content inserted with the put functions).
+    //Returns the temporary file, to be moved into place with PartFile::commit, and the size and digest of the content
+    pub(crate) fn store(&self, reader: impl Read, int_file_path: &Path) -> Result<(PartFile, u64, String), Box<dyn Error>>{
+        let part_file = PartFile::new(int_file_path);
+        let (size, digest) = self.write_part("", reader, None, &part_file, &FetchOptions::default(), false)?;
+        Ok((part_file, size, digest.digest))
+    }
+
+    //ASYNC Write the content of reader to a temporary file for int_file_path (content inserted with the put functions).
+    //Returns the temporary file, to be moved into place with PartFile::commit_async, and the size and digest of the content
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn store_async(&self, mut reader: impl AsyncRead + Unpin, int_file_path: &Path) -> Result<(PartFile, u64, String), Box<dyn Error>>{
+        let part_file = PartFile::new(int_file_path);
+        let mut file = nonblocking::FileWriter::create(&part_file.path, self.permissions.file_mode_for(false)).await?;
+        let mut hasher = ContentHasher::new(None);
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut size = 0u64;
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read]).await?;
+            hasher.update(&buffer[..read]);
+            size += read as u64;
+        }
+        file.finish().await?;
+        Ok((part_file, size, hasher.finish("")?.digest))
+    }
+
+    ///Helper Method. Stores a local source (file:// URL or data: URI) the way download_file stores a download: streamed into a
+    ///temporary file, checked against the expected checksum and moved into place. Local sources have no validators,
+    ///so refreshing them always reads them again.
+    ///
+    ///#Parameters
+    /// * url: The file:// URL or data: URI, recorded in the entry metadata
+    /// * source: The parsed source
+    /// * int_file_path: A reference to a PathBuf specifying where the content should be saved.
+    /// * options: Fetch options (progress observer, cancellation, checksum, hard linking of files)
+    ///
+    ///#Returns
+    /// *   Result<Download, Box<dyn Error>>: Returns the entry metadata, or an error if the source cannot be read or the file creation fails.
+    pub(crate) fn ingest_local(&self, url: &str, source: LocalSource, int_file_path: &Path, options: &FetchOptions) -> Result<Download, Box<dyn Error>>{
+        //Fails when cancelled or past the deadline
+        options.request_timeout()?;
+        let part_file = PartFile::new(int_file_path);
+        let mut headers = HeaderMap::new();
+        let authenticated = options.auth.is_some();
+        //A hard link keeps the mode of the source: files readable beyond the policy mode are copied instead
+        let linkable = |path: &Path| !permissions::is_wider_than(path, self.permissions.file_mode_for(authenticated)).unwrap_or(true);
+        let (size, digest) = match source {
+            LocalSource::File(path) if options.hardlink && linkable(&path) && fs::hard_link(&path, &part_file.path).is_ok() => {
+                let mut hasher = ContentHasher::new(options.checksum.as_ref());
+                let size = hasher.read_from(fs::File::open(&part_file.path)?)?;
+                (size, hasher.finish(url)?)
+            },
+            LocalSource::File(path) => {
+                let file = fs::File::open(&path).map_err(|e| get_error!("ingest_local","Unable to read '{:?}': {}", path, e))?;
+                let length = file.metadata()?.len();
+                self.write_part(url, file, Some(length), &part_file, options, authenticated)?
+            },
+            LocalSource::Data { content_type, data } => {
+                headers.insert(CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
+                self.write_part(url, data.as_slice(), Some(data.len() as u64), &part_file, options, authenticated)?
+            },
+        };
+
+        part_file.commit(int_file_path)?;
+        let mut metadata = EntryMetadata::from_response("", url, size, &headers);
+        digest.record(&mut metadata);
+        Ok(Download::Modified(metadata))
+    }
+
+    //Send a request with the given credentials
+    #[cfg(feature = "blocking")]
+    fn send_request(&self, url: &str, options: &FetchOptions, auth: Option<&Auth>, validators: Option<&EntryMetadata>) -> Result<reqwest::blocking::Response, Box<dyn Error>>{
+        let plan = RequestPlan::new(url, options, auth, validators)?;
+        let mut request_builder = HTTP_BLOCKING_CLIENT.request(plan.method, plan.url).headers(plan.headers);
+        if let Some(body) = plan.body{
+            request_builder = request_builder.body(body);
+        }
+        if let Some(timeout) = plan.timeout{
+            request_builder = request_builder.timeout(timeout);
+        }
+        Ok(request_builder.send()?)
+    }
+
+    //Provider to call when the request was rejected (401/403) and a credential provider is set
+    fn rejected_provider(&self, url: &str, status: StatusCode) -> Option<CredentialProvider>{
+        let provider = self.credentials.as_ref()?;
+        if status != StatusCode::UNAUTHORIZED && status != StatusCode::FORBIDDEN {
+            return None
+        }
+        log_verbose!("refreshed_credentials","Request rejected ({}), refreshing credentials for '{}'", status, url);
+        Some(provider.clone())
+    }
+
+    //Credentials to retry with when the request was rejected (401/403) and a credential provider is set
+    #[cfg(feature = "blocking")]
+    fn refreshed_credentials(&self, url: &str, options: &FetchOptions, status: StatusCode) -> Result<Option<Auth>, Box<dyn Error>>{
+        let Some(provider) = self.rejected_provider(url, status) else {
+            return Ok(None)
+        };
+        self.keep_refreshed(url, options, provider(url, status.as_u16()))
+    }
+
+    //ASYNC Credentials to retry with when the request was rejected (401/403). The provider may block: it runs on the blocking pool
+    #[cfg(feature = "tokio")]
+    async fn refreshed_credentials_async(&self, url: &str, options: &FetchOptions, status: StatusCode) -> Result<Option<Auth>, Box<dyn Error>>{
+        let Some(provider) = self.rejected_provider(url, status) else {
+            return Ok(None)
+        };
+        let thread_url = url.to_owned();
+        let refreshed = nonblocking::unblock(move || provider(&thread_url, status.as_u16())).await;
+        self.keep_refreshed(url, options, refreshed)
+    }
+
+    //Keep the refreshed credentials for the later requests sending the credentials of the options to the same server
+    fn keep_refreshed(&self, url: &str, options: &FetchOptions, refreshed: Result<Auth, Box<dyn Error>>) -> Result<Option<Auth>, Box<dyn Error>>{
+        let auth = refreshed.map_err(|e| get_error!("refreshed_credentials","Unable to refresh credentials for '{}': {}", url, e))?;
+        self.refreshed_auth.set(url, options.auth.as_ref(), auth.clone());
+        Ok(Some(auth))
+    }
+}
+
+//Request sent to fetch a URL, built once from the fetch options for both HTTP clients: method, URL (with the query
+//parameters and query credentials), headers (credentials, request and option headers, validators), body and timeout
+struct RequestPlan {
+    method: Method,
+    url: Url,
+    headers: HeaderMap,
+    body: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+}
+
+impl RequestPlan {
+    //validators: Metadata of the cached file when revalidating. Makes the request conditional (If-None-Match/If-Modified-Since)
+    fn new(url: &str, options: &FetchOptions, auth: Option<&Auth>, validators: Option<&EntryMetadata>) -> Result<Self, Box<dyn Error>>{
+        let mut parsed_url = Url::parse(url)?;
+        let auth = auth.map(Auth::resolve).transpose()?;
+        if let Some(a) = &auth{
+            a.apply_to_url(&mut parsed_url);
+        }
+        let request = options.request.as_ref();
+        if let Some(r) = request.filter(|r| !r.query.is_empty()){
+            parsed_url.query_pairs_mut().extend_pairs(&r.query);
+        }
+
+        let mut headers = HeaderMap::new();
+        let extra_headers = request.iter().flat_map(|r| &r.headers).chain(&options.headers).map(|(n, v)| (n.as_str(), v.as_str()));
+        for (name, value) in auth.as_ref().and_then(ResolvedAuth::header).into_iter().chain(extra_headers){
+            headers.append(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+        }
+        if let Some(etag) = validators.and_then(|v| v.etag.as_deref()){
+            headers.insert(IF_NONE_MATCH, HeaderValue::from_str(etag)?);
+        }
+        if let Some(last_modified) = validators.and_then(|v| v.last_modified.as_deref()){
+            headers.insert(IF_MODIFIED_SINCE, HeaderValue::from_str(last_modified)?);
+        }
+
+        Ok(Self {
+            method: request.map_or(Method::GET, |r| r.method.clone()),
+            url: parsed_url,
+            headers,
+            body: request.and_then(|r| r.body.clone()),
+            timeout: options.request_timeout()?,
+        })
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(all(test, feature = "async", not(feature = "tokio")))]
+mod download_tests {
+    use std::{
+        fs,
+        sync::Arc,
+        task::{Context, Poll, Wake, Waker},
+    };
+
+    use super::*;
+    use crate::{
+        fs_io::nonblocking,
+        metadata::EntryFilter,
+        put::PutOptions,
+        test_server::{TestResponse, TestServer},
+        test_support::TempCache,
+    };
+
+    //Minimal executor: polls the future on the current thread, no runtime involved
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct ThreadWaker(thread::Thread);
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_without_runtime() {
+        let server = TestServer::start(|_| TestResponse::ok(b"no runtime"));
+        let local_cache = TempCache::new("download_agnostic");
+        let url = server.url("/agnostic");
+
+        let p = block_on(local_cache.get_local_file_path_async(&url)).unwrap();
+        assert_eq!(fs::read(&p).unwrap(), b"no runtime");
+        assert_eq!(block_on(local_cache.get_local_file_path_async(&url)).unwrap(), p);
+        assert_eq!(server.request_count(), 1);
+    }
+
+    #[test]
+    fn test_file_operations_off_the_executor() {
+        let caller = thread::current().id();
+        let worker = block_on(nonblocking::unblock(|| Ok(thread::current().id()))).unwrap();
+        assert_ne!(worker, caller);
+
+        let local_cache = TempCache::new("download_agnostic_io");
+        local_cache.put_bytes("kept", b"x", &PutOptions::new()).unwrap();
+        assert_eq!(block_on(local_cache.list_entries_async(&EntryFilter::new())).unwrap().len(), 1);
+        block_on(local_cache.invalidate_cache_async("kept")).unwrap();
+        assert!(block_on(local_cache.invalidate_cache_async("kept")).is_err());
+        assert_eq!(local_cache.file_count(), 0);
+    }
+}
+
+#[cfg(test)]
+mod request_plan_tests {
+    use super::*;
+    use crate::request::FetchRequest;
+
+    #[test]
+    fn test_request_plan() {
+        let request = FetchRequest::post().with_header("X-Tenant", "t1").with_query("q", "a b").with_body(b"{}");
+        let options = FetchOptions::new().with_request(request).with_header("Accept", "text/csv").with_timeout(Duration::from_secs(5));
+        let validators = EntryMetadata { etag: Some("\"v1\"".to_owned()), last_modified: Some("Thu, 01 Jan 1970 00:00:00 GMT".to_owned()), ..Default::default() };
+
+        let plan = RequestPlan::new("https://example.com/data?x=1", &options, Some(&Auth::query("key", "k1")), Some(&validators)).unwrap();
+
+        assert_eq!(plan.method, Method::POST);
+        assert_eq!(plan.url.as_str(), "https://example.com/data?x=1&key=k1&q=a+b");
+        assert_eq!(plan.headers["x-tenant"], "t1");
+        assert_eq!(plan.headers["accept"], "text/csv");
+        assert_eq!(plan.headers[IF_NONE_MATCH], "\"v1\"");
+        assert_eq!(plan.headers[IF_MODIFIED_SINCE], "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(plan.body.as_deref(), Some(&b"{}"[..]));
+        assert_eq!(plan.timeout, Some(Duration::from_secs(5)));
+
+        let plan = RequestPlan::new("https://example.com/data", &FetchOptions::new(), Some(&Auth::bearer("t")), None).unwrap();
+        assert_eq!(plan.method, Method::GET);
+        assert_eq!(plan.headers.len(), 1);
+        assert_eq!(plan.headers["authorization"], "Bearer t");
+        assert!(plan.body.is_none() && plan.timeout.is_none());
+        assert!(RequestPlan::new("https://example.com", &FetchOptions::new().with_header("Bad Name", "v"), None, None).is_err());
+    }
+}
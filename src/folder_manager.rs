@@ -1,59 +1,100 @@
-use std::{error::Error, fs, path::PathBuf};
+use std::{collections::HashMap, env, error::Error, path::PathBuf, sync::Arc};
 
-use once_cell::sync::Lazy;
+use bt_logger::get_error;
 
+use crate::permissions;
 
-///The get_local_usr_data_path function determines the appropriate local user data directory for an application based on the operating system, 
-///and optionally creates the directory structure if it doesn't exist.
+///Kind of per-user directory, following the XDG base directory specification on Linux and the native conventions elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DirKind {
+    ///Application data
+    /// Windows: %LOCALAPPDATA%, macOS: ~/Library/Application Support, Linux: $XDG_DATA_HOME or ~/.local/share,
+    /// Android: home directory, iOS: ~/Documents
+    Data,
+    ///Configuration files
+    /// Windows: %APPDATA%, macOS: ~/Library/Application Support, Linux: $XDG_CONFIG_HOME or ~/.config,
+    /// Android: home directory, iOS: ~/Library/Preferences
+    Config,
+    ///Downloaded or regenerable files
+    /// Windows: %LOCALAPPDATA% (the application folder is followed by Cache), macOS: ~/Library/Caches,
+    /// Linux: $XDG_CACHE_HOME or ~/.cache, Android: ~/cache, iOS: ~/Library/Caches
+    Cache,
+    ///State that should persist between restarts but is not important enough for Data (logs, history)
+    /// Windows: %LOCALAPPDATA%, macOS: ~/Library/Application Support, Linux: $XDG_STATE_HOME or ~/.local/state,
+    /// Android: home directory, iOS: ~/Library/Application Support
+    State,
+    ///Runtime files (sockets, pid files)
+    /// Linux: $XDG_RUNTIME_DIR, falling back to the temporary directory. Other platforms: temporary directory
+    Runtime,
+    ///Temporary files
+    /// All platforms: std::env::temp_dir ($TMPDIR, %TEMP%, /tmp)
+    Temp,
+}
 
-///A lazy static variable that determines the base user data directory path based on the target operating system. 
-///This variable is initialized only when first accessed and uses the Lazy initialization pattern for efficiency.
-/// Windows: Uses the %LOCALAPPDATA% environment variable, falling back to current directory
-/// macOS: Uses the user's home directory joined with Library/Application Support
-/// Linux: Uses either $XDG_DATA_HOME or $HOME/.local/share, falling back to current directory
-/// Android: Uses the home directory (placeholder for sandboxed environment)
-/// iOS: Uses the home directory joined with Documents
-/// Other/Unknown Platforms: Falls back to current directory
-static DATA_PATH: Lazy<PathBuf> = Lazy::new(|| {
+//Base directory of the given kind for the target operating system, reading the environment through var.
+//When the environment does not provide it, falls back to the current directory (temporary directory for Runtime and Temp)
+#[allow(clippy::needless_return)] //Only one platform block is compiled, each one returns its own base path
+fn platform_dir(kind: DirKind, env_var: &EnvLookup) -> PathBuf {
     const FALLBACK_DIR: &str = ".";
+    let var = |name: &str| env_var(name).filter(|v| !v.is_empty());
+    #[cfg(any(target_os = "macos", target_os = "android", target_os = "ios"))]
+    let home = || PathBuf::from(var("HOME").unwrap_or_else(|| FALLBACK_DIR.to_string()));
 
     #[cfg(target_os = "windows")]
     {
-        use std::{env, path::PathBuf};
-        let base = env::var("LOCALAPPDATA").unwrap_or_else(|_| FALLBACK_DIR.to_string());
-        return PathBuf::from(base); 
+        let local = || PathBuf::from(var("LOCALAPPDATA").unwrap_or_else(|| FALLBACK_DIR.to_string()));
+        return match kind {
+            DirKind::Config => PathBuf::from(var("APPDATA").unwrap_or_else(|| FALLBACK_DIR.to_string())),
+            DirKind::Runtime | DirKind::Temp => env::temp_dir(),
+            DirKind::Data | DirKind::Cache | DirKind::State => local(),
+        };
     }
 
     #[cfg(target_os = "macos")]
     {
-        let base = env::var("HOME").unwrap_or_else(|_| FALLBACK_DIR.to_string());
-        return PathBuf::from(base)
-            .join("Library")
-            .join("Application Support");
+        return match kind {
+            DirKind::Cache => home().join("Library").join("Caches"),
+            DirKind::Runtime | DirKind::Temp => env::temp_dir(),
+            DirKind::Data | DirKind::Config | DirKind::State => home().join("Library").join("Application Support"),
+        };
     }
 
     #[cfg(target_os = "linux")]
     {
-        use std::env;
-
-        let base = env::var("XDG_DATA_HOME")
-            .or_else(|_| env::var("HOME").map(|h| format!("{}/.local/share", h)))
-            .unwrap_or_else(|_| FALLBACK_DIR.to_string());
-        return PathBuf::from(base);
+        let xdg = |name: &str, default: &str| var(name).map(PathBuf::from).unwrap_or_else(|| match var("HOME") {
+            Some(h) => PathBuf::from(h).join(default),
+            None => PathBuf::from(FALLBACK_DIR),
+        });
+        return match kind {
+            DirKind::Data => xdg("XDG_DATA_HOME", ".local/share"),
+            DirKind::Config => xdg("XDG_CONFIG_HOME", ".config"),
+            DirKind::Cache => xdg("XDG_CACHE_HOME", ".cache"),
+            DirKind::State => xdg("XDG_STATE_HOME", ".local/state"),
+            DirKind::Runtime => var("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(env::temp_dir),
+            DirKind::Temp => env::temp_dir(),
+        };
     }
 
     #[cfg(target_os = "android")]
     {
         // Android apps typically use context-specific storage, but here's a fallback
-        let base = env::var("HOME").unwrap_or_else(|_| FALLBACK_DIR.to_string());
-        return PathBuf::from(base);//.join(app_name);
+        return match kind {
+            DirKind::Cache => home().join("cache"),
+            DirKind::Runtime | DirKind::Temp => env::temp_dir(),
+            DirKind::Data | DirKind::Config | DirKind::State => home(),
+        };
     }
 
     #[cfg(target_os = "ios")]
     {
         // iOS apps use sandboxed directories; this is a placeholder
-        let base = env::var("HOME").unwrap_or_else(|_| FALLBACK_DIR.to_string());
-        return PathBuf::from(base).join("Documents");//.join(app_name);
+        return match kind {
+            DirKind::Data => home().join("Documents"),
+            DirKind::Config => home().join("Library").join("Preferences"),
+            DirKind::Cache => home().join("Library").join("Caches"),
+            DirKind::State => home().join("Library").join("Application Support"),
+            DirKind::Runtime | DirKind::Temp => env::temp_dir(),
+        };
     }
 
     #[cfg(not(any(
@@ -65,9 +106,225 @@ static DATA_PATH: Lazy<PathBuf> = Lazy::new(|| {
     )))]
     {
         // Fallback for unknown platforms
-        PathBuf::from(FALLBACK_DIR)
+        let _ = var;
+        match kind {
+            DirKind::Runtime | DirKind::Temp => env::temp_dir(),
+            _ => PathBuf::from(FALLBACK_DIR),
+        }
+    }
+}
+
+type EnvLookup = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+///Resolves the per-user base directories (see DirKind). The environment (XDG_DATA_HOME, HOME, LOCALAPPDATA...) is read
+///every time a path is resolved, so changes made while the process runs are honoured.
+///A resolver can also be built with fixed base directories or its own environment lookup and passed to
+///BTCache::new_with_resolver to control the paths of one instance without touching the process environment.
+#[derive(Clone)]
+pub struct DirResolver {
+    env: Arc<EnvLookup>,
+    bases: HashMap<DirKind, PathBuf>,
+    sanitize: bool,
+    dir_mode: Option<u32>,
+}
+
+impl Default for DirResolver {
+    fn default() -> Self {
+        Self { env: Arc::new(|name: &str| env::var(name).ok()), bases: HashMap::new(), sanitize: false, dir_mode: None }
+    }
+}
+
+impl DirResolver {
+    ///Resolver reading the process environment at call time
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Resolver reading the environment variables through the given function instead of the process environment
+    ///
+    ///#Parameters
+    /// * lookup: Returns the value of an environment variable, None when it is not set
+    pub fn with_env<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        Self { env: Arc::new(lookup), bases: HashMap::new(), sanitize: false, dir_mode: None }
+    }
+
+    ///Use a fixed base directory for one kind of directory instead of the platform one
+    ///
+    ///#Parameters
+    /// * kind: Kind of directory to override
+    /// * base: Base directory, the application folder and subfolder are added after it
+    pub fn with_base<P: Into<PathBuf>>(mut self, kind: DirKind, base: P) -> Self {
+        self.bases.insert(kind, base.into());
+        self
+    }
+
+    ///Sanitize the application and subfolder names (see sanitize_folder_name) instead of rejecting invalid ones
+    pub fn with_sanitized_names(mut self, sanitize: bool) -> Self {
+        self.sanitize = sanitize;
+        self
+    }
+
+    ///Unix mode (e.g. 0o700) of the folders created by DirResolver::path. None (default) keeps the process umask
+    pub fn with_dir_mode(mut self, mode: Option<u32>) -> Self {
+        self.dir_mode = mode;
+        self
     }
-});
+
+    ///Value of an environment variable as seen by this resolver
+    pub fn var(&self, name: &str) -> Option<String> {
+        (self.env)(name)
+    }
+
+    ///Base directory of the given kind
+    pub fn base_dir(&self, kind: DirKind) -> PathBuf {
+        match self.bases.get(&kind) {
+            Some(b) => b.clone(),
+            None => platform_dir(kind, self.env.as_ref()),
+        }
+    }
+
+    ///Same as get_local_usr_path, resolving the base directory with this resolver
+    ///# Parameters
+    /// * kind: Kind of directory (data, config, cache, state, runtime or temp)
+    /// * app_folder_name: An optional string slice (Option<&str>) that specifies the application folder name. If None or empty, no application-specific subdirectory is created.
+    /// * subfolder: An optional string slice (Option<&str>) that specifies a subfolder within the application directory. If None or empty, no subfolder is added.
+    /// * create_if_not_exists: A boolean flag that determines whether to create the directory structure if it doesn't exist.
+    ///
+    /// Returns
+    ///    Result<String, Box<dyn Error>>: Returns a String containing the full path as a string on success, or an error if:
+    ///     Directory creation fails when create_if_not_exists is true
+    pub fn path(&self, kind: DirKind, app_folder_name: Option<&str>, subfolder: Option<&str>, create_if_not_exists: bool) -> Result<String, Box<dyn Error>>{
+        let mut d_path = self.base_dir(kind);
+        if let Some (asf) = app_folder_name && !asf.trim().is_empty() {
+            d_path = d_path.join(self.folder_name(asf.trim())?);
+            if kind == DirKind::Cache && let Some(acf) = APP_CACHE_FOLDER {
+                d_path = d_path.join(acf);
+            }
+        }
+
+        if let Some(sf) = subfolder && !sf.trim().is_empty() {
+            d_path = d_path.join(self.folder_name(sf.trim())?);
+        }
+
+        let path = d_path.to_string_lossy().into_owned();
+        if create_if_not_exists{
+             permissions::create_dir_all(&d_path, self.dir_mode)?;
+        }
+
+        Ok(path)
+    }
+
+    fn folder_name(&self, name: &str) -> Result<String, Box<dyn Error>> {
+        if self.sanitize {
+            Ok(sanitize_folder_name(name))
+        } else {
+            validate_folder_name(name)?;
+            Ok(name.to_owned())
+        }
+    }
+}
+
+//Characters rejected in folder names on every platform, so a name valid on one system is valid on all of them
+const INVALID_NAME_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+//Device names reserved by Windows, alone or followed by an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+const MAX_NAME_LEN: usize = 255;
+
+fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name);
+    RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem))
+}
+
+///Check that an application or subfolder name is a single, portable folder name.
+///Rejected: empty names, "." and "..", path separators (so absolute paths and traversal are not possible),
+///control characters (including NUL), the characters < > : " | ? *, names ending with a dot or a space,
+///Windows reserved device names (CON, PRN, AUX, NUL, COM1-9, LPT1-9) and names longer than 255 bytes.
+///
+///# Parameters
+/// * name: Folder name to check
+///
+/// Returns
+///    Result<(), Box<dyn Error>>: Ok when the name is valid, an error describing the problem otherwise
+pub fn validate_folder_name(name: &str) -> Result<(), Box<dyn Error>> {
+    let problem = if name.is_empty() {
+        Some("the name is empty")
+    } else if name == "." || name == ".." {
+        Some("relative path components are not allowed")
+    } else if name.contains(['/', '\\']) {
+        Some("path separators are not allowed (absolute paths and traversal)")
+    } else if name.chars().any(|c| c.is_control()) {
+        Some("control characters are not allowed")
+    } else if name.contains(INVALID_NAME_CHARS) {
+        Some("the characters < > : \" | ? * are not allowed")
+    } else if name.ends_with('.') || name.ends_with(' ') {
+        Some("the name cannot end with a dot or a space")
+    } else if is_reserved_name(name) {
+        Some("the name is reserved on Windows")
+    } else if name.len() > MAX_NAME_LEN {
+        Some("the name is too long")
+    } else {
+        None
+    };
+
+    match problem {
+        Some(p) => Err(get_error!("validate_folder_name","Invalid folder name '{}': {}", name.escape_debug(), p).into()),
+        None => Ok(()),
+    }
+}
+
+///Turn any string into a valid folder name (see validate_folder_name): invalid characters and separators are replaced with '_',
+///trailing dots and spaces are removed, reserved names and "."/".." get a '_' prefix and the name is truncated to 255 bytes.
+///
+///# Parameters
+/// * name: Name to sanitize
+///
+/// Returns
+///    String: A folder name accepted by validate_folder_name
+pub fn sanitize_folder_name(name: &str) -> String {
+    let mut clean: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_control() || INVALID_NAME_CHARS.contains(&c) { '_' } else { c })
+        .collect();
+    //Truncate on a char boundary before stripping, so the truncation cannot leave a trailing dot or space
+    while clean.len() > MAX_NAME_LEN {
+        clean.pop();
+    }
+    while clean.ends_with('.') || clean.ends_with(' ') {
+        clean.pop();
+    }
+    if clean.is_empty() || is_reserved_name(&clean) {
+        clean.insert(0, '_');
+    }
+    clean
+}
+
+//Folder added after the application folder in the cache directory (Windows keeps caches in %LOCALAPPDATA%\<app>\Cache)
+#[cfg(target_os = "windows")]
+const APP_CACHE_FOLDER: Option<&str> = Some("Cache");
+#[cfg(not(target_os = "windows"))]
+const APP_CACHE_FOLDER: Option<&str> = None;
+
+///This function constructs a complete file system path inside the per-user directory of the given kind.
+///The environment is read on every call (see DirResolver)
+///# Parameters
+/// * kind: Kind of directory (data, config, cache, state, runtime or temp)
+/// * app_folder_name: An optional string slice (Option<&str>) that specifies the application folder name. If None or empty, no application-specific subdirectory is created.
+/// * subfolder: An optional string slice (Option<&str>) that specifies a subfolder within the application directory. If None or empty, no subfolder is added.
+/// * create_if_not_exists: A boolean flag that determines whether to create the directory structure if it doesn't exist.
+///
+/// Returns
+///    Result<String, Box<dyn Error>>: Returns a String containing the full path as a string on success, or an error if:
+///     Directory creation fails when create_if_not_exists is true
+pub fn get_local_usr_path(kind: DirKind, app_folder_name: Option<&str>, subfolder: Option<&str>, create_if_not_exists: bool) -> Result<String, Box<dyn Error>>{
+    DirResolver::default().path(kind, app_folder_name, subfolder, create_if_not_exists)
+}
 
 ///This function constructs a complete file system path for application data storage
 ///# Parameters
@@ -77,28 +334,35 @@ static DATA_PATH: Lazy<PathBuf> = Lazy::new(|| {
 ///
 /// Returns
 ///    Result<String, Box<dyn Error>>: Returns a String containing the full path as a string on success, or an error if:
+///     app_folder_name or subfolder is not a valid folder name (see validate_folder_name)
 ///     Directory creation fails when create_if_not_exists is true
-///     Path conversion to string fails
 pub fn get_local_usr_data_path(app_folder_name: Option<&str>, subfolder: Option<&str>, create_if_not_exists: bool) -> Result<String, Box<dyn Error>>{
-    let mut d_path = DATA_PATH.to_path_buf();
-    if let Some (asf) = app_folder_name{
-        if asf.trim().len() > 0 {
-             d_path = d_path.join(asf.trim());
-        }
-    }
+    get_local_usr_path(DirKind::Data, app_folder_name, subfolder, create_if_not_exists)
+}
 
-    if let Some(sf) = subfolder{
-        if sf.trim().len() > 0 {
-            d_path = d_path.join(sf.trim());
-        }
-    }
+///Same as get_local_usr_data_path, for configuration files (DirKind::Config)
+pub fn get_local_usr_config_path(app_folder_name: Option<&str>, subfolder: Option<&str>, create_if_not_exists: bool) -> Result<String, Box<dyn Error>>{
+    get_local_usr_path(DirKind::Config, app_folder_name, subfolder, create_if_not_exists)
+}
 
-    let path = d_path.to_string_lossy().into_owned();
-    if create_if_not_exists{
-         fs::create_dir_all(d_path)?;
-    }
+///Same as get_local_usr_data_path, for application cache storage (downloaded or regenerable files, DirKind::Cache)
+pub fn get_local_usr_cache_path(app_folder_name: Option<&str>, subfolder: Option<&str>, create_if_not_exists: bool) -> Result<String, Box<dyn Error>>{
+    get_local_usr_path(DirKind::Cache, app_folder_name, subfolder, create_if_not_exists)
+}
+
+///Same as get_local_usr_data_path, for state files such as logs or history (DirKind::State)
+pub fn get_local_usr_state_path(app_folder_name: Option<&str>, subfolder: Option<&str>, create_if_not_exists: bool) -> Result<String, Box<dyn Error>>{
+    get_local_usr_path(DirKind::State, app_folder_name, subfolder, create_if_not_exists)
+}
 
-    Ok(path)
+///Same as get_local_usr_data_path, for runtime files such as sockets or pid files (DirKind::Runtime)
+pub fn get_local_usr_runtime_path(app_folder_name: Option<&str>, subfolder: Option<&str>, create_if_not_exists: bool) -> Result<String, Box<dyn Error>>{
+    get_local_usr_path(DirKind::Runtime, app_folder_name, subfolder, create_if_not_exists)
+}
+
+///Same as get_local_usr_data_path, for temporary files (DirKind::Temp)
+pub fn get_local_usr_temp_path(app_folder_name: Option<&str>, subfolder: Option<&str>, create_if_not_exists: bool) -> Result<String, Box<dyn Error>>{
+    get_local_usr_path(DirKind::Temp, app_folder_name, subfolder, create_if_not_exists)
 }
 
 //*************** */
@@ -106,18 +370,17 @@ pub fn get_local_usr_data_path(app_folder_name: Option<&str>, subfolder: Option<
 //************** */
 #[cfg(test)]
 mod info_app_tests {
-    use regex::Regex;
+    use std::fs;
 
     use super::*;
+    use crate::test_support::TempDir;
     const APP_NAME: &str = "bt_file_cache";
 
     #[test]
     fn test_get_app_path_no_create_success() {
         let subfolder = "db";
-        let re = Regex::new(r"^/home/.*/\.local/share/bt_file_cache/db").unwrap();
         let df = get_local_usr_data_path(Some(APP_NAME), Some(subfolder), false).unwrap();
-        //assert_eq!(get_local_usr_data_path(Some("db"), false).unwrap(),"");
-        assert!(re.is_match(&df));
+        assert_eq!(PathBuf::from(df), data_home().join(APP_NAME).join(subfolder));
     }
 
     #[test]
@@ -130,9 +393,98 @@ mod info_app_tests {
         #[test]
     fn test_get_app_path_create_success() {
         let subfolder = "db.test"; //Should not be able to create this folder
-        let re = Regex::new(r"^/home/.*/\.local/share/bt_file_cache/db.test").unwrap();        
         let df = get_local_usr_data_path(Some(APP_NAME), Some(subfolder), true);
         assert!(df.is_ok());
-        assert!(re.is_match(&df.unwrap()));
+        assert_eq!(PathBuf::from(df.unwrap()), data_home().join(APP_NAME).join(subfolder));
+    }
+
+    //Linux data directory of the process environment: $XDG_DATA_HOME or $HOME/.local/share
+    fn data_home() -> PathBuf {
+        match env::var("XDG_DATA_HOME") {
+            Ok(d) if !d.is_empty() => PathBuf::from(d),
+            _ => PathBuf::from(env::var("HOME").unwrap()).join(".local/share"),
+        }
+    }
+
+    #[test]
+    fn test_get_cache_path_no_create_success() {
+        let resolver = home_resolver("/home/tester");
+        let df = resolver.path(DirKind::Cache, Some(APP_NAME), Some("db"), false).unwrap();
+        assert_eq!(df, "/home/tester/.cache/bt_file_cache/db");
+    }
+
+    #[test]
+    fn test_get_xdg_paths_no_create_success() {
+        let resolver = home_resolver("/home/tester");
+        let config = resolver.path(DirKind::Config, Some(APP_NAME), None, false).unwrap();
+        let state = resolver.path(DirKind::State, Some(APP_NAME), Some("logs"), false).unwrap();
+        let temp = resolver.path(DirKind::Temp, Some(APP_NAME), None, false).unwrap();
+        assert_eq!(config, "/home/tester/.config/bt_file_cache");
+        assert_eq!(state, "/home/tester/.local/state/bt_file_cache/logs");
+        assert_eq!(PathBuf::from(temp), env::temp_dir().join(APP_NAME));
+    }
+
+    //Resolver seeing only HOME, so the XDG variables of the machine running the tests are ignored
+    fn home_resolver(home: &'static str) -> DirResolver {
+        DirResolver::with_env(move |name| (name == "HOME").then(|| home.to_owned()))
+    }
+
+    #[test]
+    fn test_get_runtime_path() {
+        let runtime = PathBuf::from(get_local_usr_runtime_path(Some(APP_NAME), None, false).unwrap());
+        let expected = env::var("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(|_| env::temp_dir());
+        assert_eq!(runtime, expected.join(APP_NAME));
+    }
+
+    #[test]
+    fn test_resolver_reads_injected_env() {
+        let resolver = DirResolver::with_env(|name| match name {
+            "XDG_DATA_HOME" => Some("/srv/data".to_owned()),
+            "HOME" => Some("/home/sandbox".to_owned()),
+            _ => None,
+        });
+        assert_eq!(resolver.path(DirKind::Data, Some(APP_NAME), Some("db"), false).unwrap(), "/srv/data/bt_file_cache/db");
+        assert_eq!(resolver.path(DirKind::Config, Some(APP_NAME), None, false).unwrap(), "/home/sandbox/.config/bt_file_cache");
+    }
+
+    #[test]
+    fn test_resolver_with_base() {
+        let base = TempDir::new("resolver");
+        let resolver = DirResolver::new().with_base(DirKind::State, base.path()).with_dir_mode(Some(0o700));
+        let p = resolver.path(DirKind::State, Some(APP_NAME), Some("logs"), true).unwrap();
+        assert_eq!(PathBuf::from(&p), base.join(APP_NAME).join("logs"));
+        assert!(PathBuf::from(&p).is_dir());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&p).unwrap().permissions().mode() & 0o777, 0o700);
+        }
+    }
+
+    #[test]
+    fn test_invalid_folder_names() {
+        for name in ["..", "../../etc", "/etc", "a\\b", "a\0b", "con", "LPT1.txt", "name.", "a:b", "a*"] {
+            assert!(validate_folder_name(name).is_err(), "{:?} should be rejected", name);
+        }
+        assert!(get_local_usr_data_path(Some("../../etc"), None, false).is_err());
+        assert!(get_local_usr_cache_path(Some(APP_NAME), Some("/tmp"), false).is_err());
+        assert!(validate_folder_name("db.test").is_ok());
+        assert!(validate_folder_name("My App 2").is_ok());
+    }
+
+    #[test]
+    fn test_sanitized_folder_names() {
+        assert_eq!(sanitize_folder_name("../../etc"), ".._.._etc");
+        assert_eq!(sanitize_folder_name(".."), "_");
+        assert_eq!(sanitize_folder_name("a:b?\0"), "a_b__");
+        assert_eq!(sanitize_folder_name("CON"), "_CON");
+        assert_eq!(sanitize_folder_name("name. "), "name");
+        let long_name = format!("{}.{}", "a".repeat(254), "b".repeat(45));
+        assert_eq!(long_name.len(), 300);
+        assert_eq!(sanitize_folder_name(&long_name), "a".repeat(254));
+        let long_name = format!("{}. {}", "é".repeat(127), "b".repeat(171));
+        assert_eq!(sanitize_folder_name(&long_name), "é".repeat(127));
+        let resolver = DirResolver::with_env(|_| Some("/srv".to_owned())).with_sanitized_names(true);
+        assert_eq!(resolver.path(DirKind::Data, Some("/etc"), Some("x/y"), false).unwrap(), "/srv/_etc/x_y");
     }
 }
@@ -0,0 +1,122 @@
+//File operations used by the fetch API (see api.rs), in a blocking and a non blocking flavour with the same names.
+//With the "tokio" feature the non blocking operations run on the tokio blocking pool (tokio::fs, spawn_blocking)
+//so a slow disk does not stall the runtime. With "async" only they run with std::fs on a background thread the task waits for.
+
+//Blocking flavour, used by the sync functions and to store local sources (file:// and data: URLs)
+pub(crate) mod blocking {
+    use std::{fs, io, path::Path};
+
+    pub(crate) use std::fs::{exists, remove_file, rename};
+    #[cfg(feature = "blocking")]
+    pub(crate) use std::fs::read;
+
+    use crate::permissions;
+
+    //Writer of a new file
+    pub(crate) struct FileWriter(fs::File);
+
+    impl FileWriter {
+        //Create (or truncate) a file with the given mode
+        pub(crate) fn create(path: &Path, mode: Option<u32>) -> io::Result<Self> {
+            permissions::create_file(path, mode).map(Self)
+        }
+
+        pub(crate) fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+            io::Write::write_all(&mut self.0, buf)
+        }
+
+        //Flush and close the file
+        pub(crate) fn finish(mut self) -> io::Result<()> {
+            io::Write::flush(&mut self.0)
+        }
+    }
+}
+
+//Non blocking flavour, used by the async functions
+#[cfg(feature = "tokio")]
+pub(crate) mod nonblocking {
+    use std::{error::Error, io, path::Path};
+
+    use tokio::io::AsyncWriteExt;
+    pub(crate) use tokio::fs::{read, remove_file, rename, try_exists as exists};
+
+    use crate::permissions;
+
+    //Run a blocking operation (folder scan, metadata update) on the blocking pool
+    pub(crate) async fn unblock<T, F>(operation: F) -> Result<T, Box<dyn Error>>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, Box<dyn Error>> + Send + 'static,
+    {
+        tokio::task::spawn_blocking(move || operation().map_err(|e| e.to_string())).await?.map_err(|e| e.into())
+    }
+
+    //Writer of a new file
+    pub(crate) struct FileWriter(tokio::fs::File);
+
+    impl FileWriter {
+        //Create (or truncate) a file with the given mode
+        pub(crate) async fn create(path: &Path, mode: Option<u32>) -> io::Result<Self> {
+            let path = path.to_path_buf();
+            let file = tokio::task::spawn_blocking(move || permissions::create_file(&path, mode)).await.map_err(io::Error::other)??;
+            Ok(Self(tokio::fs::File::from_std(file)))
+        }
+
+        pub(crate) async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+            self.0.write_all(buf).await
+        }
+
+        //Flush and close the file. Pending writes are only guaranteed to be on disk once flushed
+        pub(crate) async fn finish(mut self) -> io::Result<()> {
+            self.0.flush().await
+        }
+    }
+}
+
+//Non blocking flavour without tokio (runtime agnostic "async" build): the blocking operations run on a background thread
+//and the task only waits for their result, the way downloads are run (see download.rs), so any executor keeps running
+#[cfg(all(feature = "async", not(feature = "tokio")))]
+pub(crate) mod nonblocking {
+    use std::{error::Error, io, path::Path, thread};
+
+    use futures_channel::oneshot;
+
+    use super::blocking;
+
+    //Run a blocking operation (folder scan, metadata update) on a background thread
+    pub(crate) async fn unblock<T, F>(operation: F) -> Result<T, Box<dyn Error>>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, Box<dyn Error>> + Send + 'static,
+    {
+        run(move || operation().map_err(|e| e.to_string())).await?.map_err(|e| e.into())
+    }
+
+    pub(crate) async fn exists(path: impl AsRef<Path>) -> io::Result<bool> {
+        let path = path.as_ref().to_path_buf();
+        run(move || blocking::exists(path)).await?
+    }
+
+    pub(crate) async fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let path = path.as_ref().to_path_buf();
+        run(move || blocking::read(path)).await?
+    }
+
+    pub(crate) async fn remove_file(path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref().to_path_buf();
+        run(move || blocking::remove_file(path)).await?
+    }
+
+    //Run operation on its own thread and wait for its result without blocking the executor
+    async fn run<T, F>(operation: F) -> io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        thread::Builder::new().name("bt_file_cache-io".to_owned()).spawn(move || {
+            let _ = sender.send(operation());
+        })?;
+        receiver.await.map_err(|_| io::Error::other("Blocking operation ended without a result"))
+    }
+}
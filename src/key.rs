@@ -0,0 +1,166 @@
+use std::{fmt, sync::Arc};
+
+use reqwest::Url;
+
+type KeyFn = dyn Fn(&str) -> String + Send + Sync;
+
+///Derivation of the cache key from the URL or name given to the BTCache functions.
+///The derived key is then hashed (SHA3-512, base64 URL safe) to build the cache file name.
+///
+///The default strategy (KeyStrategy::raw) uses the URL or name verbatim, as previous versions did.
+///Strategies can be combined, e.g. `KeyStrategy::normalized().ignore_query_params(&["utm_*", "fbclid"])`.
+#[derive(Clone, Default)]
+pub struct KeyStrategy {
+    normalize: bool,
+    ignored_params: Vec<String>,
+    custom: Option<Arc<KeyFn>>,
+}
+
+impl fmt::Debug for KeyStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyStrategy")
+            .field("normalize", &self.normalize)
+            .field("ignored_params", &self.ignored_params)
+            .field("custom", &self.custom.is_some())
+            .finish()
+    }
+}
+
+impl KeyStrategy {
+    ///Use the URL or name verbatim (default)
+    pub fn raw() -> Self {
+        Self::default()
+    }
+
+    ///Normalize URLs so equivalent spellings share one entry: scheme and host are lowercased, the default port is removed,
+    ///the fragment is stripped and the query parameters are sorted. Keys that are not URLs (names) are used verbatim.
+    pub fn normalized() -> Self {
+        Self { normalize: true, ..Self::default() }
+    }
+
+    ///Custom key derivation. The closure receives the URL or name (after the normalization and ignored parameters, if any)
+    ///and returns the key to hash.
+    pub fn custom<F>(derive: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        Self { custom: Some(Arc::new(derive)), ..Self::default() }
+    }
+
+    ///Query parameters removed from URLs before computing the key (e.g. tracking parameters).
+    ///A name ending with '*' matches every parameter starting with the text before it (e.g. "utm_*").
+    ///
+    ///#Parameters
+    /// * params: Names of the query parameters to ignore
+    pub fn ignore_query_params(mut self, params: &[&str]) -> Self {
+        self.ignored_params.extend(params.iter().map(|p| p.to_string()));
+        self
+    }
+
+    ///Apply a custom derivation after the normalization and ignored parameters of this strategy
+    pub fn with_custom<F>(mut self, derive: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.custom = Some(Arc::new(derive));
+        self
+    }
+
+    ///Key derived from a URL or name
+    ///
+    ///#Parameters
+    /// * url_name_id: URL or name given to the BTCache functions
+    ///
+    /// #Returns
+    ///     * String: The key that is hashed to build the cache file name
+    pub fn derive_key(&self, url_name_id: &str) -> String {
+        let key = if self.normalize || !self.ignored_params.is_empty() {
+            self.rewrite_url(url_name_id).unwrap_or_else(|| url_name_id.to_owned())
+        } else {
+            url_name_id.to_owned()
+        };
+        match &self.custom {
+            Some(derive) => derive(&key),
+            None => key,
+        }
+    }
+
+    //Normalize and/or drop ignored query parameters. None when the key is not a URL
+    fn rewrite_url(&self, url_name_id: &str) -> Option<String> {
+        let mut url = Url::parse(url_name_id).ok()?;
+        if url.cannot_be_a_base() {
+            return None;
+        }
+        let mut pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(k, _)| !self.is_ignored(k))
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        if self.normalize {
+            //Url::parse already lowercases the scheme and host and removes the default port
+            url.set_fragment(None);
+            pairs.sort();
+        }
+        if pairs.is_empty() {
+            url.set_query(None);
+        } else if self.normalize || url.query_pairs().count() != pairs.len() {
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        Some(url.to_string())
+    }
+
+    fn is_ignored(&self, param: &str) -> bool {
+        self.ignored_params.iter().any(|p| match p.strip_suffix('*') {
+            Some(prefix) => param.starts_with(prefix),
+            None => p == param,
+        })
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod key_tests {
+    use super::*;
+    #[cfg(feature = "blocking")]
+    use crate::{
+        test_server::{TestResponse, TestServer},
+        test_support::TempCache,
+    };
+
+    #[test]
+    fn test_normalized_keys() {
+        let strategy = KeyStrategy::normalized();
+        assert_eq!(strategy.derive_key("HTTPS://Example.COM:443/a?c=2&b=1#top"), "https://example.com/a?b=1&c=2");
+        assert_eq!(strategy.derive_key("https://example.com/a?b=1&c=2"), "https://example.com/a?b=1&c=2");
+        assert_eq!(strategy.derive_key("http://example.com:8080/a?"), "http://example.com:8080/a");
+        assert_eq!(strategy.derive_key("my report"), "my report");
+        assert_eq!(KeyStrategy::raw().derive_key("https://example.com/a?c=2&b=1"), "https://example.com/a?c=2&b=1");
+    }
+
+    #[test]
+    fn test_ignored_params_and_custom() {
+        let strategy = KeyStrategy::raw().ignore_query_params(&["utm_*", "fbclid"]);
+        assert_eq!(strategy.derive_key("https://x.com/a?id=3&utm_source=mail&fbclid=9"), "https://x.com/a?id=3");
+        assert_eq!(strategy.derive_key("https://x.com/a?utm_medium=web"), "https://x.com/a");
+
+        let strategy = KeyStrategy::normalized().with_custom(|k| k.replace("/v2/", "/"));
+        assert_eq!(strategy.derive_key("https://x.com/v2/file?b=1&a=2"), "https://x.com/file?a=2&b=1");
+        assert_eq!(KeyStrategy::custom(|k| k.to_lowercase()).derive_key("NAME"), "name");
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_cache_uses_key_strategy() {
+        let server = TestServer::start(|_| TestResponse::ok(b"same content"));
+        let local_cache = TempCache::with("key", |c| c.with_key_strategy(KeyStrategy::normalized().ignore_query_params(&["utm_*"])));
+
+        let p1 = local_cache.get_local_file_path(&server.url("/key?b=1&a=2")).unwrap();
+        let p2 = local_cache.get_local_file_path(&server.url("/key?a=2&b=1&utm_campaign=x#frag")).unwrap();
+
+        assert_eq!(p1, p2);
+        assert_eq!(server.request_count(), 1);
+        assert!(local_cache.entry_info(&server.url("/key?a=2&b=1")).unwrap().is_some());
+    }
+}
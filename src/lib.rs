@@ -1,2 +1,32 @@
+#[cfg(not(any(feature = "blocking", feature = "async")))]
+compile_error!("bt_file_cache needs the \"blocking\" or the \"async\" feature");
+#[cfg(all(feature = "async", not(feature = "tokio"), not(feature = "blocking")))]
+compile_error!("the runtime agnostic \"async\" feature needs \"blocking\" (or use \"tokio\")");
+
 pub mod folder_manager;
-pub mod cache;
\ No newline at end of file
+mod download;
+mod fs_io;
+mod api;
+pub mod auth;
+pub mod cache;
+pub mod cancel;
+mod checksum;
+pub mod key;
+pub mod maintenance;
+pub mod metadata;
+pub mod options;
+pub mod permissions;
+pub mod prefetch;
+pub mod progress;
+pub mod put;
+pub mod request;
+mod source;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod stats;
+mod vary;
+
+#[cfg(test)]
+mod test_server;
+#[cfg(test)]
+mod test_support;
@@ -0,0 +1,274 @@
+use std::{error::Error, fs, io::Read, path::Path};
+
+use bt_logger::log_error;
+use sha3::{Digest, Sha3_256};
+
+#[cfg(feature = "async")]
+use crate::fs_io::nonblocking;
+use crate::{
+    cache::BTCache,
+    metadata::{CacheEntry, EntryFilter, EntryMetadata, hex_digest},
+};
+
+///Result of the integrity check of one entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyStatus {
+    ///The file matches the size and digest recorded when it was downloaded
+    Valid,
+    ///The file does not match its metadata. The reason describes the mismatch
+    Corrupted(String),
+    ///No digest was recorded for this entry (cached by an older version), it cannot be verified
+    Unverified,
+}
+
+///Entry checked by BTCache::verify_entries
+#[derive(Debug, Clone)]
+pub struct EntryVerification {
+    pub entry: CacheEntry,
+    pub status: VerifyStatus,
+}
+
+impl BTCache {
+    ///Removes the entries whose expiry (Cache-Control max-age or Expires header) has passed.
+    ///
+    /// #Returns
+    /// Result<usize, Box<dyn Error>>
+    ///     * Success: Ok(usize) - Number of entries removed
+    ///     * Error: Err(Box<dyn Error>) - The cache folder cannot be read
+    pub fn purge_expired(&self) -> Result<usize, Box<dyn Error>> {
+        let (expired, mut kept): (Vec<CacheEntry>, Vec<CacheEntry>) = self.list_entries(&EntryFilter::default())?.into_iter().partition(|e| e.is_expired());
+        let mut purged_keys = Vec::new();
+        for entry in expired {
+            match fs::remove_file(&entry.path) {
+                Ok(()) => {
+                    EntryMetadata::remove(&entry.path);
+                    self.stats.record_eviction();
+                    purged_keys.push(entry.key);
+                }
+                Err(e) => {
+                    log_error!("purge_expired","Unable to remove expired entry '{}': {}", entry.key, e);
+                    kept.push(entry);
+                }
+            }
+        }
+        //The Vary record of a key goes with its last variant
+        for key in purged_keys.iter().filter(|k| !kept.iter().any(|e| &e.key == *k)) {
+            self.remove_vary_record(key);
+        }
+        Ok(purged_keys.len())
+    }
+
+    ///ASYNC Removes the entries whose expiry (Cache-Control max-age or Expires header) has passed.
+    ///
+    /// #Returns
+    /// Result<usize, Box<dyn Error>>
+    ///     * Success: Ok(usize) - Number of entries removed
+    ///     * Error: Err(Box<dyn Error>) - The cache folder cannot be read
+    #[cfg(feature = "async")]
+    pub async fn purge_expired_async(&self) -> Result<usize, Box<dyn Error>> {
+        let cache = self.shared();
+        nonblocking::unblock(move || cache.purge_expired()).await
+    }
+
+    ///Checks every cached file against the size and SHA3-256 digest recorded when it was downloaded.
+    ///
+    /// #Returns
+    /// Result<Vec<EntryVerification>, Box<dyn Error>>
+    ///     * Success: Ok(Vec<EntryVerification>) - One result per entry, sorted by key
+    ///     * Error: Err(Box<dyn Error>) - The cache folder cannot be read
+    pub fn verify_entries(&self) -> Result<Vec<EntryVerification>, Box<dyn Error>> {
+        let entries = self.list_entries(&EntryFilter::default())?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| {
+                let status = Self::verify_file(&entry.path);
+                EntryVerification { entry, status }
+            })
+            .collect())
+    }
+
+    ///ASYNC Checks every cached file against the size and SHA3-256 digest recorded when it was downloaded.
+    ///
+    /// #Returns
+    /// Result<Vec<EntryVerification>, Box<dyn Error>>
+    ///     * Success: Ok(Vec<EntryVerification>) - One result per entry, sorted by key
+    ///     * Error: Err(Box<dyn Error>) - The cache folder cannot be read
+    #[cfg(feature = "async")]
+    pub async fn verify_entries_async(&self) -> Result<Vec<EntryVerification>, Box<dyn Error>> {
+        let cache = self.shared();
+        nonblocking::unblock(move || cache.verify_entries()).await
+    }
+
+    ///Total size in bytes of the cached files
+    ///
+    /// #Returns
+    /// Result<u64, Box<dyn Error>>
+    ///     * Success: Ok(u64) - Sum of the size of every cached file
+    ///     * Error: Err(Box<dyn Error>) - The cache folder cannot be read
+    pub fn total_size(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(self.list_entries(&EntryFilter::default())?.iter().map(|e| e.size).sum())
+    }
+
+    ///ASYNC Total size in bytes of the cached files
+    ///
+    /// #Returns
+    /// Result<u64, Box<dyn Error>>
+    ///     * Success: Ok(u64) - Sum of the size of every cached file
+    ///     * Error: Err(Box<dyn Error>) - The cache folder cannot be read
+    #[cfg(feature = "async")]
+    pub async fn total_size_async(&self) -> Result<u64, Box<dyn Error>> {
+        let cache = self.shared();
+        nonblocking::unblock(move || cache.total_size()).await
+    }
+
+    fn verify_file(path: &Path) -> VerifyStatus {
+        let Ok(metadata) = EntryMetadata::load(path) else {
+            return VerifyStatus::Unverified;
+        };
+        let Some(expected) = metadata.digest else {
+            return VerifyStatus::Unverified;
+        };
+        match Self::file_digest(path) {
+            Ok((size, _)) if size != metadata.size => {
+                VerifyStatus::Corrupted(format!("Size mismatch. Expected {} bytes, found {}", metadata.size, size))
+            }
+            Ok((_, digest)) if digest != expected => VerifyStatus::Corrupted("Digest mismatch".to_owned()),
+            Ok(_) => VerifyStatus::Valid,
+            Err(e) => VerifyStatus::Corrupted(format!("Unable to read file: {}", e)),
+        }
+    }
+
+    //Size and hex encoded SHA3-256 digest of a file
+    pub(crate) fn file_digest(path: &Path) -> Result<(u64, String), Box<dyn Error>> {
+        let mut file = fs::File::open(path)?;
+        let mut hasher = Sha3_256::new();
+        let mut buffer = [0u8; 64 * 1024];
+        let mut size = 0u64;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            size += read as u64;
+        }
+        Ok((size, hex_digest(hasher)))
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod maintenance_tests {
+    #[cfg(feature = "blocking")]
+    use std::{fs::OpenOptions, io::Write};
+
+    use super::*;
+    #[cfg(feature = "blocking")]
+    use crate::{options::FetchOptions, vary::vary_record_path};
+    use crate::{test_server::{TestResponse, TestServer}, test_support::TempCache};
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_verify_detects_corruption() {
+        let server = TestServer::start(|_| TestResponse::ok(b"verified content"));
+        let local_cache = TempCache::new("maintenance_verify");
+        let (good, bad) = (server.url("/verify-good"), server.url("/verify-bad"));
+        local_cache.get_local_file_path(&good).unwrap();
+        let bad_path = local_cache.get_local_file_path(&bad).unwrap();
+        OpenOptions::new().append(true).open(&bad_path).unwrap().write_all(b"!").unwrap();
+
+        let results = local_cache.verify_entries().unwrap();
+
+        let status = |key: &str| results.iter().find(|r| r.entry.key == key).map(|r| r.status.clone());
+        assert_eq!(status(&good), Some(VerifyStatus::Valid));
+        assert!(matches!(status(&bad), Some(VerifyStatus::Corrupted(_))));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_purge_expired() {
+        let server = TestServer::start(|req| match req.path() {
+            p if p.starts_with("/purge-old") => TestResponse::ok(b"old").header("Expires", "Thu, 01 Jan 1970 00:00:00 GMT"),
+            _ => TestResponse::ok(b"fresh").header("Cache-Control", "max-age=3600"),
+        });
+        let local_cache = TempCache::new("maintenance_purge");
+        let (old, fresh) = (server.url("/purge-old"), server.url("/purge-fresh"));
+        local_cache.get_local_file_path(&old).unwrap();
+        local_cache.get_local_file_path(&fresh).unwrap();
+
+        let removed = local_cache.purge_expired().unwrap();
+
+        assert_eq!(removed, 1);
+        assert!(local_cache.entry_info(&old).unwrap().is_none());
+        assert!(local_cache.entry_info(&fresh).unwrap().is_some());
+        assert_eq!(local_cache.stats().evictions, removed as u64);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_purge_expired_removes_vary_record() {
+        let server = TestServer::start(|req| {
+            let expires = match req.path() {
+                "/purge-vary-fresh" if req.header("Accept-Language") == Some("en") => "Thu, 01 Jan 2099 00:00:00 GMT",
+                _ => "Thu, 01 Jan 1970 00:00:00 GMT",
+            };
+            TestResponse::ok(b"variant").header("Vary", "Accept-Language").header("Expires", expires)
+        });
+        let local_cache = TempCache::new("maintenance_purge_vary");
+        let (old, partly) = (server.url("/purge-vary-old"), server.url("/purge-vary-fresh"));
+        for lang in ["en", "fr"] {
+            let options = FetchOptions::new().with_header("Accept-Language", lang);
+            local_cache.get_local_file_path_with_options(&old, &old, &options).unwrap();
+            local_cache.get_local_file_path_with_options(&partly, &partly, &options).unwrap();
+        }
+        let record = |key: &str| vary_record_path(&local_cache.get_file(key));
+        assert!(record(&old).exists() && record(&partly).exists());
+
+        assert_eq!(local_cache.purge_expired().unwrap(), 3);
+
+        assert!(!record(&old).exists());
+        assert!(record(&partly).exists());
+        assert_eq!(local_cache.file_count(), 3);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_total_size_async() {
+        let server = TestServer::start(|_| TestResponse::ok(&[1u8; 1000]));
+        let local_cache = TempCache::new("maintenance_size_async");
+        let url = server.url("/size");
+        local_cache.get_local_file_path_async(&url).await.unwrap();
+
+        let total = local_cache.total_size_async().await.unwrap();
+
+        assert_eq!(total, 1000);
+        let info = local_cache.entry_info_async(&url).await.unwrap().unwrap();
+        assert_eq!(info.size, 1000);
+        assert_eq!(info.url.as_deref(), Some(url.as_str()));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_maintenance_async_shares_stats() {
+        let server = TestServer::start(|req| match req.path() {
+            "/old_async" => TestResponse::ok(b"old").header("Expires", "Thu, 01 Jan 1970 00:00:00 GMT"),
+            _ => TestResponse::ok(b"fresh"),
+        });
+        let local_cache = TempCache::new("maintenance_async");
+        let (old, fresh) = (server.url("/old_async"), server.url("/fresh_async"));
+        local_cache.get_local_file_path_async(&old).await.unwrap();
+        local_cache.get_local_file_path_async(&fresh).await.unwrap();
+        local_cache.get_local_file_path_async(&fresh).await.unwrap();
+
+        assert_eq!(local_cache.purge_expired_async().await.unwrap(), 1);
+        let results = local_cache.verify_entries_async().await.unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].status, VerifyStatus::Valid);
+        let stats = local_cache.stats();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.hits, 1);
+    }
+}
@@ -0,0 +1,480 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bt_logger::log_error;
+use reqwest::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, EXPIRES, HeaderMap, HeaderName, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+
+#[cfg(feature = "async")]
+use crate::fs_io::nonblocking;
+use crate::{cache::{BTCache, temp_path}, vary::vary_from_headers};
+
+//Extension of the metadata file stored next to each cached file
+const METADATA_EXTENSION: &str = "meta";
+
+//Metadata stored next to each cached file (<hash>.meta) as JSON. Times are seconds since UNIX epoch.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct EntryMetadata {
+    pub key: String,
+    pub url: Option<String>,
+    pub size: u64,
+    pub fetched_at: u64,
+    pub last_access: u64,
+    pub expires_at: Option<u64>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
+    //Hex encoded SHA3-256 of the file content, used to verify the integrity of the cached file
+    #[serde(default)]
+    pub digest: Option<String>,
+    //Checksum verified with another algorithm when the content was fetched ("sha256:<hex>", "sha512:<hex>", see FetchOptions::with_checksum)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    //Request headers the response varies on (Vary header), lowercased and sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vary: Vec<String>,
+}
+
+impl EntryMetadata {
+    //Build the metadata of a file that was just downloaded
+    pub(crate) fn from_response(key: &str, url: &str, size: u64, headers: &HeaderMap) -> Self {
+        let now = unix_now();
+        let header = |name: HeaderName| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_owned);
+        Self {
+            key: key.to_owned(),
+            url: Some(url.to_owned()),
+            size,
+            fetched_at: now,
+            last_access: now,
+            expires_at: expiry_from_headers(headers, now),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            content_type: header(CONTENT_TYPE),
+            digest: None,
+            checksum: None,
+            vary: vary_from_headers(headers),
+        }
+    }
+
+    //Build the metadata of an existing cache file that has none (entries cached by older versions)
+    fn from_file(key: &str, url: Option<&str>, int_file_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file_info = fs::metadata(int_file_path)?;
+        let fetched_at = file_info.modified().map(to_unix).unwrap_or_else(|_| unix_now());
+        Ok(Self {
+            key: key.to_owned(),
+            url: url.map(str::to_owned),
+            size: file_info.len(),
+            fetched_at,
+            last_access: unix_now(),
+            ..Default::default()
+        })
+    }
+
+    pub(crate) fn load(int_file_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read(metadata_path(int_file_path))?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    //Written to a temporary file first so readers never see a partial metadata file
+    pub(crate) fn save(&self, int_file_path: &Path) -> Result<(), Box<dyn Error>> {
+        let meta_path = metadata_path(int_file_path);
+        let tmp_path = temp_path(&meta_path, "tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        //Same permissions as the cached file, metadata of a private entry stays private
+        if let Ok(file_info) = fs::metadata(int_file_path) {
+            let _ = fs::set_permissions(&tmp_path, file_info.permissions());
+        }
+        if let Err(e) = fs::rename(&tmp_path, &meta_path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    //Record a cache hit. Entries without metadata get one built from the file itself.
+    pub(crate) fn touch(int_file_path: &Path, key: &str, url: &str) {
+        let meta = match Self::load(int_file_path) {
+            Ok(mut m) => {
+                m.last_access = unix_now();
+                Ok(m)
+            }
+            Err(_) => Self::from_file(key, Some(url), int_file_path),
+        };
+        if let Err(e) = meta.and_then(|m| m.save(int_file_path)) {
+            log_error!("touch","Unable to update metadata of '{:?}': {}", int_file_path, e);
+        }
+    }
+
+    pub(crate) fn remove(int_file_path: &Path) {
+        let _ = fs::remove_file(metadata_path(int_file_path));
+    }
+}
+
+pub(crate) fn metadata_path(int_file_path: &Path) -> PathBuf {
+    int_file_path.with_extension(METADATA_EXTENSION)
+}
+
+///Information about one cached file, returned by BTCache::list_entries.
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    ///key: URL or name/id used to store the entry. Entries cached by older versions have no record of it; the file name is used instead.
+    pub key: String,
+    ///url: Source URL of the cached file, when known
+    pub url: Option<String>,
+    ///path: Full path of the cached file
+    pub path: PathBuf,
+    ///size: Size of the cached file in bytes
+    pub size: u64,
+    ///fetched_at: When the file was downloaded
+    pub fetched_at: SystemTime,
+    ///last_access: Last time the entry was served by the cache
+    pub last_access: SystemTime,
+    ///expires_at: Expiry reported by the server (Cache-Control max-age or Expires header), if any.
+    ///It is informational: entries are kept until invalidated or purged (BTCache::purge_expired).
+    pub expires_at: Option<SystemTime>,
+    ///etag: ETag header returned by the server, if any
+    pub etag: Option<String>,
+    ///content_type: Content-Type header returned by the server, if any
+    pub content_type: Option<String>,
+}
+
+impl CacheEntry {
+    ///Time elapsed since the file was downloaded
+    pub fn age(&self) -> Duration {
+        SystemTime::now().duration_since(self.fetched_at).unwrap_or_default()
+    }
+
+    ///Returns true when the entry has an expiry in the past
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|e| e <= SystemTime::now())
+    }
+}
+
+///Filter applied by BTCache::list_entries. Every criterion is optional; the default filter lists all entries.
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    key_prefix: Option<String>,
+    min_age: Option<Duration>,
+    max_age: Option<Duration>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+}
+
+impl EntryFilter {
+    ///Constructor. Matches every entry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Only entries whose key (URL or name/id) starts with the given prefix
+    pub fn with_key_prefix(mut self, prefix: &str) -> Self {
+        self.key_prefix = Some(prefix.to_owned());
+        self
+    }
+
+    ///Only entries downloaded at least `age` ago
+    pub fn with_min_age(mut self, age: Duration) -> Self {
+        self.min_age = Some(age);
+        self
+    }
+
+    ///Only entries downloaded at most `age` ago
+    pub fn with_max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    ///Only entries of at least `size` bytes
+    pub fn with_min_size(mut self, size: u64) -> Self {
+        self.min_size = Some(size);
+        self
+    }
+
+    ///Only entries of at most `size` bytes
+    pub fn with_max_size(mut self, size: u64) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    fn matches(&self, entry: &CacheEntry) -> bool {
+        let age = entry.age();
+        self.key_prefix.as_ref().is_none_or(|p| entry.key.starts_with(p.as_str()))
+            && self.min_age.is_none_or(|a| age >= a)
+            && self.max_age.is_none_or(|a| age <= a)
+            && self.min_size.is_none_or(|s| entry.size >= s)
+            && self.max_size.is_none_or(|s| entry.size <= s)
+    }
+}
+
+impl BTCache {
+    ///Lists the entries stored in the cache folder that match the filter.
+    ///
+    /// #Parameters
+    /// * filter: Criteria (key prefix, age, size) the entries must match. Use EntryFilter::default() to list everything.
+    ///
+    /// #Returns
+    /// Result<Vec<CacheEntry>, Box<dyn Error>>
+    ///     * Success: Ok(Vec<CacheEntry>) - The matching entries, sorted by key
+    ///     * Error: Err(Box<dyn Error>) - The cache folder cannot be read
+    pub fn list_entries(&self, filter: &EntryFilter) -> Result<Vec<CacheEntry>, Box<dyn Error>> {
+        let mut entries = Vec::new();
+        for dir_entry in fs::read_dir(&self.folder_path)? {
+            let dir_entry = dir_entry?;
+            let file_name = dir_entry.file_name().to_string_lossy().into_owned();
+            //Cache files are plain hashes. Anything with an extension is metadata or a temporary file
+            if file_name.contains('.') || !dir_entry.file_type()?.is_file() {
+                continue;
+            }
+            let path = dir_entry.path();
+            let entry = match Self::read_entry(&file_name, &path) {
+                Ok(e) => e,
+                Err(e) => {
+                    log_error!("list_entries","Unable to read cache entry '{:?}': {}", path, e);
+                    continue;
+                }
+            };
+            if filter.matches(&entry) {
+                entries.push(entry);
+            }
+        }
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(entries)
+    }
+
+    ///Returns the information of a single entry, or None when it is not cached.
+    ///
+    /// #Parameters
+    /// * url_name_id: URL or name/id used to store the entry
+    ///
+    /// #Returns
+    /// Result<Option<CacheEntry>, Box<dyn Error>>
+    ///     * Success: Ok(Some(CacheEntry)) when cached, Ok(None) otherwise
+    ///     * Error: Err(Box<dyn Error>) - The cached file exists but its information cannot be read
+    pub fn entry_info(&self, url_name_id: &str) -> Result<Option<CacheEntry>, Box<dyn Error>> {
+        let mut path = self.get_file(url_name_id);
+        if !path.try_exists()? {
+            //Entries cached per variant (Vary header) are stored under other names
+            match self.variant_entries(url_name_id).into_iter().next() {
+                Some(variant) => path = variant,
+                None => return Ok(None),
+            }
+        }
+        let file_name = path.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+        Ok(Some(Self::read_entry(&file_name, &path)?))
+    }
+
+    ///ASYNC Returns the information of a single entry, or None when it is not cached.
+    ///
+    /// #Parameters
+    /// * url_name_id: URL or name/id used to store the entry
+    ///
+    /// #Returns
+    /// Result<Option<CacheEntry>, Box<dyn Error>>
+    ///     * Success: Ok(Some(CacheEntry)) when cached, Ok(None) otherwise
+    ///     * Error: Err(Box<dyn Error>) - The cached file exists but its information cannot be read
+    #[cfg(feature = "async")]
+    pub async fn entry_info_async(&self, url_name_id: &str) -> Result<Option<CacheEntry>, Box<dyn Error>> {
+        let (cache, url_name_id) = (self.shared(), url_name_id.to_owned());
+        nonblocking::unblock(move || cache.entry_info(&url_name_id)).await
+    }
+
+    ///ASYNC Lists the entries stored in the cache folder that match the filter.
+    ///
+    /// #Parameters
+    /// * filter: Criteria (key prefix, age, size) the entries must match. Use EntryFilter::default() to list everything.
+    ///
+    /// #Returns
+    /// Result<Vec<CacheEntry>, Box<dyn Error>>
+    ///     * Success: Ok(Vec<CacheEntry>) - The matching entries, sorted by key
+    ///     * Error: Err(Box<dyn Error>) - The cache folder cannot be read
+    #[cfg(feature = "async")]
+    pub async fn list_entries_async(&self, filter: &EntryFilter) -> Result<Vec<CacheEntry>, Box<dyn Error>> {
+        let (cache, filter) = (self.shared(), filter.clone());
+        nonblocking::unblock(move || cache.list_entries(&filter)).await
+    }
+
+    pub(crate) fn read_entry(file_name: &str, path: &Path) -> Result<CacheEntry, Box<dyn Error>> {
+        let file_info = fs::metadata(path)?;
+        let entry = match EntryMetadata::load(path) {
+            Ok(m) => CacheEntry {
+                key: m.key,
+                url: m.url,
+                path: path.to_path_buf(),
+                size: file_info.len(),
+                fetched_at: from_unix(m.fetched_at),
+                last_access: from_unix(m.last_access),
+                expires_at: m.expires_at.map(from_unix),
+                etag: m.etag,
+                content_type: m.content_type,
+            },
+            Err(_) => CacheEntry {
+                key: file_name.to_owned(),
+                url: None,
+                path: path.to_path_buf(),
+                size: file_info.len(),
+                fetched_at: file_info.modified()?,
+                last_access: file_info.accessed().or_else(|_| file_info.modified())?,
+                expires_at: None,
+                etag: None,
+                content_type: None,
+            },
+        };
+        Ok(entry)
+    }
+}
+
+//Hex encoded SHA3-256 digest
+pub(crate) fn hex_digest(hasher: Sha3_256) -> String {
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn unix_now() -> u64 {
+    to_unix(SystemTime::now())
+}
+
+pub(crate) fn to_unix(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub(crate) fn from_unix(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+//Expiry from Cache-Control max-age (preferred) or the Expires header
+fn expiry_from_headers(headers: &HeaderMap, now: u64) -> Option<u64> {
+    let cache_control = headers.get(CACHE_CONTROL).and_then(|v| v.to_str().ok()).unwrap_or("");
+    let max_age = cache_control
+        .split(',')
+        .filter_map(|d| d.trim().split_once('='))
+        .find(|(n, _)| n.trim().eq_ignore_ascii_case("max-age"))
+        .and_then(|(_, v)| v.trim().trim_matches('"').parse::<u64>().ok());
+    if let Some(secs) = max_age {
+        return Some(now + secs);
+    }
+    headers.get(EXPIRES).and_then(|v| v.to_str().ok()).and_then(parse_http_date).map(to_unix)
+}
+
+//Parse an HTTP date in the preferred IMF-fixdate format (e.g. "Sun, 06 Nov 1994 08:49:37 GMT")
+pub(crate) fn parse_http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let (_, date) = value.trim().split_once(", ")?;
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    if parts.len() != 5 || parts[4] != "GMT" {
+        return None;
+    }
+    let day: i64 = parts[0].parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == parts[1])? as i64 + 1;
+    let year: i64 = parts[2].parse().ok()?;
+    let hms: Vec<i64> = parts[3].split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    if hms.len() != 3 || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    //Days since 1970-01-01 (civil from days algorithm, proleptic Gregorian calendar)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + hms[0] * 3600 + hms[1] * 60 + hms[2];
+    u64::try_from(secs).ok().map(from_unix)
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod metadata_tests {
+    use super::*;
+    use crate::{test_server::{TestResponse, TestServer}, test_support::TempCache};
+
+    #[test]
+    fn test_parse_http_date() {
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(from_unix(784111777)));
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(UNIX_EPOCH));
+        assert_eq!(parse_http_date("Tue, 29 Feb 2028 23:59:59 GMT"), Some(from_unix(1835481599)));
+        assert!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT").is_none());
+        assert!(parse_http_date("not a date").is_none());
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_list_entries_with_metadata() {
+        let server = TestServer::start(|req| match req.path().rsplit('/').next() {
+            Some("big") => TestResponse::ok(&[0u8; 2048]).header("Cache-Control", "public, max-age=60").header("ETag", "\"v1\""),
+            _ => TestResponse::ok(b"small").header("Expires", "Thu, 01 Jan 2099 00:00:00 GMT"),
+        });
+        let local_cache = TempCache::new("metadata_list");
+        let prefix = server.url("/list/");
+        let (big, small) = (format!("{}big", prefix), format!("{}small", prefix));
+        local_cache.get_local_file_path(&big).unwrap();
+        local_cache.get_local_file_path(&small).unwrap();
+
+        let entries = local_cache.list_entries(&EntryFilter::new().with_key_prefix(&prefix)).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].key, big);
+        assert_eq!(entries[0].url.as_deref(), Some(big.as_str()));
+        assert_eq!(entries[0].size, 2048);
+        let ttl = entries[0].expires_at.unwrap().duration_since(entries[0].fetched_at).unwrap();
+        assert_eq!(ttl, Duration::from_secs(60));
+        assert_eq!(entries[1].size, 5);
+        assert_eq!(entries[1].expires_at, parse_http_date("Thu, 01 Jan 2099 00:00:00 GMT"));
+        assert!(!entries[1].is_expired());
+
+        let large = local_cache.list_entries(&EntryFilter::new().with_key_prefix(&prefix).with_min_size(1024)).unwrap();
+        assert_eq!(large.len(), 1);
+        assert_eq!(large[0].key, big);
+        let recent = local_cache.list_entries(&EntryFilter::new().with_key_prefix(&prefix).with_max_age(Duration::from_secs(3600))).unwrap();
+        assert_eq!(recent.len(), 2);
+        let old = local_cache.list_entries(&EntryFilter::new().with_key_prefix(&prefix).with_min_age(Duration::from_secs(3600))).unwrap();
+        assert!(old.is_empty());
+
+        local_cache.invalidate_cache(&big).unwrap();
+        let remaining = local_cache.list_entries(&EntryFilter::new().with_key_prefix(&prefix)).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert!(!metadata_path(&local_cache.get_file(&big)).exists());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_list_entries_async_by_name() {
+        let server = TestServer::start(|_| TestResponse::ok(b"named"));
+        let local_cache = TempCache::new("metadata_list_async");
+        let prefix = server.url("/named/");
+        let name = format!("{}file_id", prefix);
+        let url = server.url("/named_file");
+        local_cache.get_local_file_path_with_name_async(&url, &name).await.unwrap();
+
+        let entries = local_cache.list_entries_async(&EntryFilter::new().with_key_prefix(&prefix)).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, name);
+        assert_eq!(entries[0].url.as_deref(), Some(url.as_str()));
+        assert!(entries[0].expires_at.is_none());
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_hit_backfills_missing_metadata() {
+        let server = TestServer::start(|_| TestResponse::ok(b"legacy"));
+        let local_cache = TempCache::new("metadata_backfill");
+        let prefix = server.url("/legacy/");
+        let url = format!("{}file", prefix);
+        let path = local_cache.get_local_file_path(&url).unwrap();
+        EntryMetadata::remove(Path::new(&path));
+
+        local_cache.get_local_file_path(&url).unwrap();
+
+        let meta = EntryMetadata::load(Path::new(&path)).unwrap();
+        assert_eq!(meta.key, url);
+        assert_eq!(meta.size, 6);
+        assert_eq!(server.request_count(), 1);
+    }
+}
@@ -0,0 +1,145 @@
+use std::{error::Error, sync::{Arc, mpsc::Sender}, time::{Duration, Instant}};
+
+use bt_logger::get_error;
+
+use crate::{auth::Auth, cancel::CancellationToken, checksum::Checksum, progress::{DownloadProgress, ProgressCallback, progress_channel}, request::FetchRequest};
+
+///FetchOptions groups the optional settings accepted by the *_with_options functions of BTCache.
+///Start from FetchOptions::new() (or default()) and chain the with_* functions.
+#[derive(Clone, Default)]
+pub struct FetchOptions {
+    pub(crate) auth: Option<Auth>,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) progress: Option<ProgressCallback>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) cancel: Option<CancellationToken>,
+    pub(crate) request: Option<FetchRequest>,
+    pub(crate) checksum: Option<Checksum>,
+    pub(crate) hardlink: bool,
+    pub(crate) local_sources: bool,
+}
+
+impl FetchOptions {
+    ///Constructor. Every option is unset (no token, no progress observer, default timeout, no cancellation).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Access token sent as a bearer token when downloading the URL resource. Same as with_auth(Auth::bearer(token))
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.auth = Some(Auth::bearer(token));
+        self
+    }
+
+    ///Credentials sent when downloading the URL resource (bearer, basic, custom header, query parameter or callback)
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    ///Extra request header sent when downloading the URL resource (e.g. Accept, Accept-Language).
+    ///When the server answers with a Vary header listing it, its value selects the cached variant.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    ///HTTP request sent to fetch the resource (method, headers, query parameters and body) instead of a plain GET.
+    ///The request parts selected by FetchRequest are folded into the cache key (see FetchRequest::cache_key).
+    pub fn with_request(mut self, request: FetchRequest) -> Self {
+        self.request = Some(request);
+        self
+    }
+
+    ///Expected checksum of the content, hex encoded and tagged with its algorithm: "sha256:<hex>", "sha512:<hex>" or
+    ///"sha3-256:<hex>". An untagged hex digest is a SHA3-256 digest (the digest recorded in the entry metadata).
+    ///A download that does not match (or with an unknown algorithm) is rejected and leaves no entry, and a cached entry that
+    ///does not match is downloaded again. SHA-256 and SHA-512 checksums are verified while downloading and recorded in the entry
+    ///metadata, so entries cached without them are downloaded again once.
+    pub fn with_checksum(mut self, checksum: &str) -> Self {
+        self.checksum = Some(Checksum::parse(checksum));
+        self
+    }
+
+    ///Accept file:// URLs and data: URIs as sources (off by default). Only enable it for URLs the application trusts:
+    ///a file:// URL copies any file readable by the process into the cache.
+    pub fn with_local_sources(mut self, local_sources: bool) -> Self {
+        self.local_sources = local_sources;
+        self
+    }
+
+    ///Store file:// sources by hard linking the file into the cache instead of copying it (falls back to a copy when linking fails,
+    ///e.g. across file systems). The cached file then shares the content and permissions of the source file, so a source readable
+    ///beyond the mode of the PermissionPolicy (e.g. a 0644 file in a private cache) is copied instead.
+    pub fn with_hardlink(mut self, hardlink: bool) -> Self {
+        self.hardlink = hardlink;
+        self
+    }
+
+    ///Register a callback notified with the download progress (bytes downloaded, total and transfer rate).
+    ///The callback is only invoked when the file is actually downloaded, not on cache hits.
+    pub fn with_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&DownloadProgress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    ///Send the download progress updates to a channel instead of a callback
+    pub fn with_progress_channel(mut self, sender: Sender<DownloadProgress>) -> Self {
+        self.progress = Some(progress_channel(sender));
+        self
+    }
+
+    ///Maximum time allowed for each fetch using these options, from connecting until the whole body is received.
+    ///Replaces the default 10 seconds timeout of the HTTP client (it can be shorter or longer).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    ///Point in time after which fetches using these options fail. Combined with with_timeout, the earliest limit applies.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    ///Cancellation token that aborts the fetch. A cancelled download never leaves a partial entry in the cache.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    //Cache key of the URL or name fetched with these options
+    pub(crate) fn cache_key(&self, url_name_id: &str) -> String {
+        match &self.request {
+            Some(r) => r.cache_key(url_name_id),
+            None => url_name_id.to_owned(),
+        }
+    }
+
+    //Timeout to apply to the next request: the smallest of the timeout and the time left before the deadline.
+    //Fails if the deadline has already passed or the fetch was cancelled.
+    pub(crate) fn request_timeout(&self) -> Result<Option<Duration>, Box<dyn Error>> {
+        if self.is_cancelled() {
+            return Err(get_error!("request_timeout","Fetch cancelled").into())
+        }
+        let remaining = match self.deadline {
+            Some(d) => match d.checked_duration_since(Instant::now()) {
+                Some(r) if !r.is_zero() => Some(r),
+                _ => return Err(get_error!("request_timeout","Fetch deadline exceeded").into()),
+            },
+            None => None,
+        };
+        Ok(match (self.timeout, remaining) {
+            (Some(t), Some(r)) => Some(t.min(r)),
+            (t, r) => t.or(r),
+        })
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|c| c.is_cancelled())
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    path::Path,
+};
+
+///Unix permission modes applied when the cache creates its folder and files. Ignored on other platforms.
+///
+///The default policy keeps the cache private to its owner: 0700 for the folders and 0600 for the files downloaded
+///with credentials (token), while other files follow the process umask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PermissionPolicy {
+    ///dir_mode: Mode of the folders created for the cache. None keeps the process umask
+    pub dir_mode: Option<u32>,
+    ///file_mode: Mode of the cached files. None keeps the process umask
+    pub file_mode: Option<u32>,
+    ///authenticated_file_mode: Mode of the files downloaded with credentials. None uses file_mode
+    pub authenticated_file_mode: Option<u32>,
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        Self { dir_mode: Some(0o700), file_mode: None, authenticated_file_mode: Some(0o600) }
+    }
+}
+
+impl PermissionPolicy {
+    ///Policy leaving every folder and file to the process umask (behaviour of previous versions)
+    pub fn umask() -> Self {
+        Self { dir_mode: None, file_mode: None, authenticated_file_mode: None }
+    }
+
+    ///Policy keeping everything private to the owner: 0700 folders and 0600 files
+    pub fn private() -> Self {
+        Self { dir_mode: Some(0o700), file_mode: Some(0o600), authenticated_file_mode: Some(0o600) }
+    }
+
+    //Mode of a new cached file
+    pub(crate) fn file_mode_for(&self, authenticated: bool) -> Option<u32> {
+        if authenticated { self.authenticated_file_mode.or(self.file_mode) } else { self.file_mode }
+    }
+}
+
+//Create a folder (and its missing parents) with the given mode
+pub(crate) fn create_dir_all(path: &Path, mode: Option<u32>) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    if let Some(m) = mode {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(m);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    builder.create(path)
+}
+
+//Create (or truncate) a file with the given mode
+pub(crate) fn create_file(path: &Path, mode: Option<u32>) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if let Some(m) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(m);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    options.open(path)
+}
+
+//Change the mode of an existing file or folder
+pub(crate) fn set_mode(path: &Path, mode: Option<u32>) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(m) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(m))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
+//Whether a file grants permissions outside the given mode (e.g. a group readable file for a 0600 policy).
+//Always false without a mode and on non Unix platforms
+pub(crate) fn is_wider_than(path: &Path, mode: Option<u32>) -> io::Result<bool> {
+    let metadata = fs::metadata(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Ok(mode.is_some_and(|m| metadata.permissions().mode() & 0o777 & !m != 0))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (metadata, mode);
+        Ok(false)
+    }
+}
+
+///Whether a file or folder can be written by its group or by any user. Always false on non Unix platforms.
+///
+///#Parameters
+/// * path: File or folder to check
+///
+/// #Returns
+/// io::Result<bool>
+///     * Success: Ok(true) when the group or others have write permission
+///     * Error: Err(io::Error) - The path cannot be read
+pub fn is_group_or_world_writable(path: &Path) -> io::Result<bool> {
+    let metadata = fs::metadata(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Ok(metadata.permissions().mode() & 0o022 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        Ok(false)
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(all(test, unix))]
+mod permissions_tests {
+    #[cfg(feature = "async")]
+    use std::sync::Arc;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    #[cfg(feature = "blocking")]
+    use crate::options::FetchOptions;
+    use crate::{
+        cache::BTCache,
+        test_server::{TestResponse, TestServer},
+        test_support::{TempCache, TempDir},
+    };
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_default_policy() {
+        let server = TestServer::start(|_| TestResponse::ok(b"secret"));
+        let local_cache = TempCache::new("perm_default");
+        let public = server.url("/perm_public");
+        let private = server.url("/perm_private");
+
+        local_cache.get_local_file_path(&public).unwrap();
+        let p = local_cache.get_local_file_path_with_options(&private, &private, &FetchOptions::new().with_token("abc")).unwrap();
+
+        assert_eq!(mode(local_cache.folder_path()), 0o700);
+        assert_eq!(mode(Path::new(&p)), 0o600);
+        assert_eq!(mode(&crate::metadata::metadata_path(Path::new(&p))), 0o600);
+        assert!(!local_cache.check_folder_permissions().unwrap());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_private_policy_async() {
+        let server = TestServer::start(|_| TestResponse::ok(b"private"));
+        let local_cache = Arc::new(TempCache::with("perm_private", |c| c.with_permissions(PermissionPolicy::private()).unwrap()));
+
+        let p = local_cache.get_local_file_path_async(&server.url("/perm_async")).await.unwrap();
+
+        assert_eq!(mode(Path::new(&p)), 0o600);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_hardlink_respects_policy() {
+        use std::os::unix::fs::MetadataExt;
+
+        let local_cache = TempCache::with("perm_hardlink", |c| c.with_permissions(PermissionPolicy::private()).unwrap());
+        let options = FetchOptions::new().with_local_sources(true).with_hardlink(true);
+        let (shared, private) = (local_cache.dir().join("shared.txt"), local_cache.dir().join("private.txt"));
+        fs::write(&shared, "shared").unwrap();
+        fs::set_permissions(&shared, fs::Permissions::from_mode(0o644)).unwrap();
+        fs::write(&private, "private").unwrap();
+        fs::set_permissions(&private, fs::Permissions::from_mode(0o600)).unwrap();
+        let inode = |path: &Path| fs::metadata(path).unwrap().ino();
+
+        let copied = local_cache.get_local_file_path_with_options(&format!("file://{}", shared.display()), "shared", &options).unwrap();
+        let linked = local_cache.get_local_file_path_with_options(&format!("file://{}", private.display()), "private", &options).unwrap();
+
+        assert_eq!(mode(Path::new(&copied)), 0o600);
+        assert_ne!(inode(Path::new(&copied)), inode(&shared));
+        assert_eq!(mode(&shared), 0o644);
+        assert_eq!(inode(Path::new(&linked)), inode(&private));
+    }
+
+    #[test]
+    fn test_world_writable_folder_detected() {
+        let dir = TempDir::new("perm_open");
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+
+        let local_cache = BTCache::with_dir(dir.path()).unwrap().with_permissions(PermissionPolicy::umask()).unwrap();
+        assert!(local_cache.check_folder_permissions().unwrap());
+
+        let local_cache = local_cache.with_permissions(PermissionPolicy::default()).unwrap();
+        assert!(!local_cache.check_folder_permissions().unwrap());
+    }
+}
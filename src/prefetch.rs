@@ -0,0 +1,215 @@
+use std::error::Error;
+#[cfg(feature = "blocking")]
+use std::{sync::{Mutex, atomic::{AtomicUsize, Ordering}}, thread};
+
+use bt_logger::log_verbose;
+#[cfg(feature = "async")]
+use futures_util::{StreamExt, stream};
+
+use crate::cache::BTCache;
+
+///Outcome of a single prefetch item.
+pub struct PrefetchResult {
+    ///url: URL requested for this item
+    pub url: String,
+    ///name: Optional cache name/id used instead of the URL as cache key
+    pub name: Option<String>,
+    ///result: Local file path of the cached file, or the error that prevented caching it
+    pub result: Result<String, Box<dyn Error>>,
+}
+
+///Report returned by the prefetch functions. Items are listed in the same order they were submitted.
+pub struct PrefetchReport {
+    pub items: Vec<PrefetchResult>,
+}
+
+impl PrefetchReport {
+    ///Number of items successfully cached
+    pub fn success_count(&self) -> usize {
+        self.items.iter().filter(|i| i.result.is_ok()).count()
+    }
+
+    ///Number of items that failed
+    pub fn failure_count(&self) -> usize {
+        self.items.len() - self.success_count()
+    }
+
+    ///Iterator over the failed items
+    pub fn failures(&self) -> impl Iterator<Item = &PrefetchResult> {
+        self.items.iter().filter(|i| i.result.is_err())
+    }
+}
+
+impl BTCache {
+    ///Function that downloads (warms) a batch of cache entries, on up to max_concurrency threads.
+    ///Items already cached are not downloaded again. A failure on one item does not abort the batch;
+    ///every item gets its own result in the returned report.
+    ///
+    /// #Parameters:
+    ///     * items: Iterator of (url, optional file name/id, optional access token)
+    ///     * max_concurrency: Maximum number of downloads running at the same time. Zero is treated as one.
+    ///
+    /// #Returns:
+    ///     * PrefetchReport: One PrefetchResult per item, in the same order as the input
+    #[cfg(feature = "blocking")]
+    pub fn prefetch<'a, I>(&self, items: I, max_concurrency: usize) -> PrefetchReport
+    where
+        I: IntoIterator<Item = (&'a str, Option<&'a str>, Option<&'a str>)>,
+    {
+        let items: Vec<_> = items.into_iter().collect();
+        //Each worker takes the next item until none is left. Errors cross the threads as text
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<Result<String, String>>>> = Mutex::new(vec![None; items.len()]);
+        thread::scope(|scope| {
+            for _ in 0..max_concurrency.max(1).min(items.len()) {
+                scope.spawn(|| {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(&(url, name, token)) = items.get(index) else { break };
+                        let result = self.get_local_file_path_with_name_token(url, name.unwrap_or(url), token).map_err(|e| e.to_string());
+                        if let Ok(mut r) = results.lock() {
+                            r[index] = Some(result);
+                        }
+                    }
+                });
+            }
+        });
+
+        let results = results.into_inner().unwrap_or_default();
+        let items = items
+            .into_iter()
+            .zip(results)
+            .map(|((url, name, _), result)| PrefetchResult {
+                url: url.to_owned(),
+                name: name.map(str::to_owned),
+                result: result.unwrap_or_else(|| Err("Prefetch worker ended without a result".to_owned())).map_err(|e| e.into()),
+            })
+            .collect();
+        let report = PrefetchReport { items };
+        log_verbose!("prefetch","Prefetch completed. Success: {} Failed: {}", report.success_count(), report.failure_count());
+        report
+    }
+
+    ///ASYNC Function that downloads (warms) a batch of cache entries.
+    ///Items already cached are not downloaded again. A failure on one item does not abort the batch;
+    ///every item gets its own result in the returned report.
+    ///
+    /// #Parameters:
+    ///     * items: Iterator of (url, optional file name/id, optional access token)
+    ///     * max_concurrency: Maximum number of downloads running at the same time. Zero is treated as one.
+    ///
+    /// #Returns:
+    ///     * PrefetchReport: One PrefetchResult per item, in the same order as the input
+    #[cfg(feature = "async")]
+    pub async fn prefetch_async<'a, I>(&self, items: I, max_concurrency: usize) -> PrefetchReport
+    where
+        I: IntoIterator<Item = (&'a str, Option<&'a str>, Option<&'a str>)>,
+    {
+        let results = stream::iter(items)
+            .map(|(url, name, token)| async move {
+                let result = self.get_local_file_path_with_name_token_async(url, name.unwrap_or(url), token).await;
+                PrefetchResult { url: url.to_owned(), name: name.map(str::to_owned), result }
+            })
+            .buffered(max_concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+
+        let report = PrefetchReport { items: results };
+        log_verbose!("prefetch_async","Prefetch completed. Success: {} Failed: {}", report.success_count(), report.failure_count());
+        report
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod prefetch_tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::{test_server::{TestResponse, TestServer}, test_support::TempCache};
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_prefetch_on_threads() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (th_active, th_peak) = (active.clone(), peak.clone());
+        let server = TestServer::start(move |req| {
+            let now = th_active.fetch_add(1, Ordering::SeqCst) + 1;
+            th_peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            th_active.fetch_sub(1, Ordering::SeqCst);
+            match req.path() {
+                "/missing" => TestResponse::status(404, b"not found"),
+                p => TestResponse::ok(p.as_bytes()),
+            }
+        });
+        let local_cache = TempCache::new("prefetch_sync");
+        let mut urls: Vec<String> = (0..6).map(|i| server.url(&format!("/item{}", i))).collect();
+        urls.insert(2, server.url("/missing"));
+
+        let report = local_cache.prefetch(urls.iter().map(|u| (u.as_str(), Some(u.as_str()), None)), 3);
+
+        assert_eq!((report.success_count(), report.failure_count()), (6, 1));
+        assert_eq!(report.failures().next().unwrap().url, urls[2]);
+        assert!(report.items.iter().zip(&urls).all(|(item, url)| &item.url == url));
+        assert_eq!(std::fs::read(report.items[3].result.as_ref().unwrap()).unwrap(), b"/item2");
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+        assert!(peak.load(Ordering::SeqCst) > 1);
+        assert_eq!(local_cache.prefetch(Vec::new(), 0).items.len(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_prefetch_reports_each_item() {
+        let server = TestServer::start(|req| match req.path() {
+            "/missing" => TestResponse::status(404, b"not found"),
+            p => TestResponse::ok(p.as_bytes()),
+        });
+        let local_cache = TempCache::new("prefetch");
+        let (u1, u2, u3) = (server.url("/a"), server.url("/missing"), server.url("/c"));
+        let name = format!("prefetch_{}", u3);
+
+        let report = local_cache
+            .prefetch_async(vec![(u1.as_str(), None, None), (u2.as_str(), None, None), (u3.as_str(), Some(name.as_str()), None)], 2)
+            .await;
+
+        assert_eq!(report.items.len(), 3);
+        assert_eq!(report.success_count(), 2);
+        assert_eq!(report.failure_count(), 1);
+        assert_eq!(report.failures().next().unwrap().url, u2);
+        assert_eq!(report.items[2].name.as_deref(), Some(name.as_str()));
+        let cached = std::fs::read(report.items[2].result.as_ref().unwrap()).unwrap();
+        assert_eq!(cached, b"/c");
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_prefetch_respects_concurrency() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (th_active, th_peak) = (active.clone(), peak.clone());
+        let server = TestServer::start(move |_| {
+            let now = th_active.fetch_add(1, Ordering::SeqCst) + 1;
+            th_peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            th_active.fetch_sub(1, Ordering::SeqCst);
+            TestResponse::ok(b"data")
+        });
+        let local_cache = TempCache::new("prefetch_concurrency");
+        let urls: Vec<String> = (0..8).map(|i| server.url(&format!("/item{}", i))).collect();
+
+        let report = local_cache.prefetch_async(urls.iter().map(|u| (u.as_str(), None, None)), 3).await;
+
+        assert_eq!(report.success_count(), 8);
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+    }
+}
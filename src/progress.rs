@@ -0,0 +1,125 @@
+use std::{sync::{Arc, mpsc::Sender}, time::Instant};
+
+///Snapshot of a download in progress, delivered to the progress observer registered in FetchOptions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadProgress {
+    ///downloaded: Number of bytes received so far
+    pub downloaded: u64,
+    ///total: Total number of bytes to receive, taken from the Content-Length header when the server provides it
+    pub total: Option<u64>,
+    ///bytes_per_second: Average transfer rate since the download started
+    pub bytes_per_second: f64,
+}
+
+///Progress observer. Called after every chunk written to the cache file.
+pub type ProgressCallback = Arc<dyn Fn(&DownloadProgress) + Send + Sync>;
+
+///Build a progress observer that forwards every update to a channel.
+///Updates are dropped silently once the receiver is gone.
+pub fn progress_channel(sender: Sender<DownloadProgress>) -> ProgressCallback {
+    Arc::new(move |p: &DownloadProgress| {
+        let _ = sender.send(p.clone());
+    })
+}
+
+//Keeps track of the bytes received and notifies the observer (if any)
+pub(crate) struct ProgressTracker {
+    start: Instant,
+    downloaded: u64,
+    total: Option<u64>,
+    callback: Option<ProgressCallback>,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(total: Option<u64>, callback: Option<ProgressCallback>) -> Self {
+        Self { start: Instant::now(), downloaded: 0, total, callback }
+    }
+
+    pub(crate) fn downloaded(&self) -> u64 {
+        self.downloaded
+    }
+
+    pub(crate) fn advance(&mut self, bytes: usize) {
+        self.downloaded += bytes as u64;
+        if let Some(cb) = &self.callback {
+            let elapsed = self.start.elapsed().as_secs_f64();
+            let bytes_per_second = if elapsed > 0.0 { self.downloaded as f64 / elapsed } else { 0.0 };
+            cb(&DownloadProgress { downloaded: self.downloaded, total: self.total, bytes_per_second });
+        }
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod progress_tests {
+    #[cfg(feature = "blocking")]
+    use std::sync::mpsc;
+    #[cfg(feature = "async")]
+    use std::{sync::Mutex, time::Duration};
+
+    use super::*;
+    use crate::{
+        options::FetchOptions,
+        test_server::{TestResponse, TestServer},
+        test_support::TempCache,
+    };
+
+    const BODY: &[u8] = &[7u8; 4096];
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_progress_callback_async() {
+        let server = TestServer::start(|_| TestResponse::ok(BODY).slow(1024, Duration::from_millis(5)));
+        let local_cache = TempCache::new("progress_async");
+        let updates = Arc::new(Mutex::new(Vec::<DownloadProgress>::new()));
+        let th_updates = updates.clone();
+        let options = FetchOptions::new().with_progress(move |p| th_updates.lock().unwrap().push(p.clone()));
+        let url = server.url("/progress_async");
+
+        let p = local_cache.get_local_file_path_with_options_async(&url, &url, &options).await.unwrap();
+
+        let updates = updates.lock().unwrap();
+        assert!(!updates.is_empty());
+        let last = updates.last().unwrap();
+        assert_eq!(last.downloaded, BODY.len() as u64);
+        assert_eq!(last.total, Some(BODY.len() as u64));
+        assert!(updates.windows(2).all(|w| w[0].downloaded <= w[1].downloaded));
+        assert_eq!(std::fs::read(p).unwrap(), BODY);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_progress_channel_sync() {
+        let server = TestServer::start(|_| TestResponse::ok(BODY));
+        let local_cache = TempCache::new("progress_sync");
+        let (tx, rx) = mpsc::channel();
+        let options = FetchOptions::new().with_progress_channel(tx);
+        let url = server.url("/progress_sync");
+
+        local_cache.get_local_file_path_with_options(&url, &url, &options).unwrap();
+        drop(options);
+
+        let updates: Vec<DownloadProgress> = rx.iter().collect();
+        assert_eq!(updates.last().unwrap().downloaded, BODY.len() as u64);
+        assert_eq!(updates.last().unwrap().total, Some(BODY.len() as u64));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_no_progress_on_cache_hit() {
+        let server = TestServer::start(|_| TestResponse::ok(BODY));
+        let local_cache = TempCache::new("progress_hit");
+        let url = server.url("/progress_hit");
+        local_cache.get_local_file_path(&url).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let options = FetchOptions::new().with_progress_channel(tx);
+        local_cache.get_local_file_path_with_options(&url, &url, &options).unwrap();
+        drop(options);
+
+        assert_eq!(rx.iter().count(), 0);
+        assert_eq!(server.request_count(), 1);
+    }
+}
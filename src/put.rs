@@ -0,0 +1,268 @@
+use std::{error::Error, fs, io::Read, path::Path, time::Duration};
+
+use bt_logger::{get_error, log_error};
+
+#[cfg(feature = "async")]
+use crate::fs_io::nonblocking;
+use crate::{cache::BTCache, metadata::{EntryMetadata, unix_now}};
+
+///PutOptions groups the optional metadata of the content inserted with the put functions of BTCache.
+///Start from PutOptions::new() (or default()) and chain the with_* functions.
+#[derive(Debug, Clone, Default)]
+pub struct PutOptions {
+    url: Option<String>,
+    content_type: Option<String>,
+    ttl: Option<Duration>,
+}
+
+impl PutOptions {
+    ///Constructor. No URL, content type or time to live
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///URL the content comes from, recorded in the entry metadata (CacheEntry::url)
+    pub fn with_url(mut self, url: &str) -> Self {
+        self.url = Some(url.to_owned());
+        self
+    }
+
+    ///Media type of the content, recorded in the entry metadata (CacheEntry::content_type)
+    pub fn with_content_type(mut self, content_type: &str) -> Self {
+        self.content_type = Some(content_type.to_owned());
+        self
+    }
+
+    ///Time to live of the entry. The entry is expired after it (CacheEntry::is_expired) and removed by purge_expired.
+    ///The time to live only applies to purging: the get functions keep returning an expired entry until it is purged
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    //Metadata of the content stored under key
+    fn metadata(&self, key: &str, size: u64, digest: String) -> EntryMetadata {
+        let now = unix_now();
+        EntryMetadata {
+            key: key.to_owned(),
+            url: self.url.clone(),
+            size,
+            fetched_at: now,
+            last_access: now,
+            expires_at: self.ttl.map(|t| now + t.as_secs()),
+            content_type: self.content_type.clone(),
+            digest: Some(digest),
+            ..Default::default()
+        }
+    }
+}
+
+impl BTCache {
+    ///Store content in the cache under a URL or name, as if it was downloaded: later get functions with the same key
+    ///return it without any request. An existing entry (every variant when the entry varies on request headers) is replaced
+    ///once the new content is completely written: if reading the content fails, the existing entry is kept.
+    ///
+    ///#Parameters
+    /// * url_name_id: URL or name the content is stored under (same key as get_local_file_path or get_local_file_path_with_name)
+    /// * reader: Source of the content
+    /// * options: Metadata of the entry (source URL, content type, time to live)
+    ///
+    ///#Returns
+    /// * Result<String, Box<dyn Error>>: Returns the full local file path of the stored content, or an error if the content cannot be read or written
+    pub fn put_reader(&self, url_name_id: &str, reader: impl Read, options: &PutOptions) -> Result<String, Box<dyn Error>> {
+        let int_file_path = self.get_file(url_name_id);
+        let (part_file, size, digest) = self.downloader().store(reader, &int_file_path)?;
+        self.remove_variants(url_name_id)?;
+        part_file.commit(&int_file_path)?;
+        self.complete_put(&int_file_path, options.metadata(url_name_id, size, digest))
+    }
+
+    ///Store bytes in the cache under a URL or name. See put_reader
+    ///
+    ///#Parameters
+    /// * url_name_id: URL or name the content is stored under
+    /// * data: Content to store
+    /// * options: Metadata of the entry (source URL, content type, time to live)
+    ///
+    ///#Returns
+    /// * Result<String, Box<dyn Error>>: Returns the full local file path of the stored content, or an error if it cannot be written
+    pub fn put_bytes(&self, url_name_id: &str, data: &[u8], options: &PutOptions) -> Result<String, Box<dyn Error>> {
+        self.put_reader(url_name_id, data, options)
+    }
+
+    ///Store a copy of a local file in the cache under a URL or name. See put_reader
+    ///
+    ///#Parameters
+    /// * url_name_id: URL or name the content is stored under
+    /// * path: File to copy into the cache
+    /// * options: Metadata of the entry (source URL, content type, time to live)
+    ///
+    ///#Returns
+    /// * Result<String, Box<dyn Error>>: Returns the full local file path of the stored content, or an error if the file cannot be read or written
+    pub fn put_file(&self, url_name_id: &str, path: &Path, options: &PutOptions) -> Result<String, Box<dyn Error>> {
+        let file = fs::File::open(path).map_err(|e| get_error!("put_file","Unable to read '{:?}': {}", path, e))?;
+        self.put_reader(url_name_id, file, options)
+    }
+
+    ///ASYNC Store content in the cache under a URL or name. See put_reader
+    ///Only available with the "tokio" feature, the reader being a tokio::io::AsyncRead (a futures::io::AsyncRead can be adapted
+    ///with tokio_util::compat). The runtime agnostic "async" build offers put_bytes_async and put_file_async.
+    ///
+    ///#Parameters
+    /// * url_name_id: URL or name the content is stored under
+    /// * reader: Source of the content
+    /// * options: Metadata of the entry (source URL, content type, time to live)
+    ///
+    ///#Returns
+    /// * Result<String, Box<dyn Error>>: Returns the full local file path of the stored content, or an error if the content cannot be read or written
+    #[cfg(feature = "tokio")]
+    pub async fn put_reader_async(&self, url_name_id: &str, reader: impl tokio::io::AsyncRead + Unpin, options: &PutOptions) -> Result<String, Box<dyn Error>> {
+        let int_file_path = self.get_file(url_name_id);
+        let (part_file, size, digest) = self.downloader().store_async(reader, &int_file_path).await?;
+        //Variant scan, rename and metadata save on the blocking pool
+        let (cache, key, metadata) = (self.shared(), url_name_id.to_owned(), options.metadata(url_name_id, size, digest));
+        nonblocking::unblock(move || {
+            cache.remove_variants(&key)?;
+            part_file.commit(&int_file_path)?;
+            cache.complete_put(&int_file_path, metadata)
+        }).await
+    }
+
+    ///ASYNC Store bytes in the cache under a URL or name. See put_reader
+    ///
+    ///#Parameters
+    /// * url_name_id: URL or name the content is stored under
+    /// * data: Content to store
+    /// * options: Metadata of the entry (source URL, content type, time to live)
+    ///
+    ///#Returns
+    /// * Result<String, Box<dyn Error>>: Returns the full local file path of the stored content, or an error if it cannot be written
+    #[cfg(feature = "async")]
+    pub async fn put_bytes_async(&self, url_name_id: &str, data: &[u8], options: &PutOptions) -> Result<String, Box<dyn Error>> {
+        #[cfg(feature = "tokio")]
+        {
+            self.put_reader_async(url_name_id, data, options).await
+        }
+        //Runtime agnostic build: written on a background thread, as the other file operations
+        #[cfg(not(feature = "tokio"))]
+        {
+            let (cache, key, data, options) = (self.shared(), url_name_id.to_owned(), data.to_vec(), options.clone());
+            nonblocking::unblock(move || cache.put_bytes(&key, &data, &options)).await
+        }
+    }
+
+    ///ASYNC Store a copy of a local file in the cache under a URL or name. See put_reader
+    ///
+    ///#Parameters
+    /// * url_name_id: URL or name the content is stored under
+    /// * path: File to copy into the cache
+    /// * options: Metadata of the entry (source URL, content type, time to live)
+    ///
+    ///#Returns
+    /// * Result<String, Box<dyn Error>>: Returns the full local file path of the stored content, or an error if the file cannot be read or written
+    #[cfg(feature = "async")]
+    pub async fn put_file_async(&self, url_name_id: &str, path: &Path, options: &PutOptions) -> Result<String, Box<dyn Error>> {
+        #[cfg(feature = "tokio")]
+        {
+            let file = tokio::fs::File::open(path).await.map_err(|e| get_error!("put_file_async","Unable to read '{:?}': {}", path, e))?;
+            self.put_reader_async(url_name_id, file, options).await
+        }
+        //Runtime agnostic build: copied on a background thread, as the other file operations
+        #[cfg(not(feature = "tokio"))]
+        {
+            let (cache, key, path, options) = (self.shared(), url_name_id.to_owned(), path.to_path_buf(), options.clone());
+            nonblocking::unblock(move || cache.put_file(&key, &path, &options)).await
+        }
+    }
+
+    //Remove the variants and the Vary record of the key: the new content replaces them all
+    fn remove_variants(&self, url_name_id: &str) -> Result<(), Box<dyn Error>> {
+        if !self.variant_entries(url_name_id).is_empty() {
+            self.invalidate_cache(url_name_id)?;
+        }
+        self.remove_vary_record(url_name_id);
+        Ok(())
+    }
+
+    //Save the metadata of the stored content and return its path
+    fn complete_put(&self, int_file_path: &Path, metadata: EntryMetadata) -> Result<String, Box<dyn Error>> {
+        if let Err(e) = metadata.save(int_file_path) {
+            log_error!("complete_put","Unable to save metadata of '{:?}': {}", int_file_path, e);
+        }
+        self.complete_request(Ok(()), int_file_path)
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod put_tests {
+    use super::*;
+    use crate::test_support::TempCache;
+    #[cfg(feature = "blocking")]
+    use crate::{maintenance::VerifyStatus, metadata::EntryFilter};
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_put_then_get() {
+        let local_cache = TempCache::new("put");
+        let dir = local_cache.dir();
+        let url = "http://127.0.0.1:9/generated.json";
+        let options = PutOptions::new().with_url(url).with_content_type("application/json").with_ttl(Duration::from_secs(3600));
+
+        let p = local_cache.put_bytes(url, b"{}", &options).unwrap();
+        assert_eq!(local_cache.get_local_file_path(url).unwrap(), p);
+        assert_eq!(p, local_cache.get_file(url).to_str().unwrap());
+        let entry = local_cache.entry_info(url).unwrap().unwrap();
+        assert_eq!(entry.content_type.as_deref(), Some("application/json"));
+        assert_eq!(entry.size, 2);
+        assert!(!entry.is_expired() && entry.expires_at.is_some());
+
+        let source = dir.join("report.csv");
+        fs::write(&source, "a,b").unwrap();
+        let p = local_cache.put_file("report", &source, &PutOptions::new()).unwrap();
+        assert_eq!(local_cache.get_local_file_path_with_name("http://127.0.0.1:9/report", "report").unwrap(), p);
+        local_cache.put_reader("report", &b"c,d"[..], &PutOptions::new().with_ttl(Duration::ZERO)).unwrap();
+        assert_eq!(fs::read(&p).unwrap(), b"c,d");
+        assert!(local_cache.verify_entries().unwrap().iter().all(|v| v.status == VerifyStatus::Valid));
+
+        assert_eq!(local_cache.purge_expired().unwrap(), 1);
+        assert_eq!(local_cache.list_entries(&EntryFilter::new()).unwrap().len(), 1);
+        assert!(local_cache.put_file("missing", &dir.join("missing"), &PutOptions::new()).is_err());
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_put_failing_reader_keeps_entry() {
+        struct FailingReader;
+        impl Read for FailingReader {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("read failure"))
+            }
+        }
+
+        let local_cache = TempCache::new("put_failing");
+        let p = local_cache.put_bytes("kept", b"old", &PutOptions::new()).unwrap();
+        assert!(local_cache.put_reader("kept", FailingReader, &PutOptions::new()).is_err());
+        assert_eq!(fs::read(&p).unwrap(), b"old");
+        assert!(local_cache.entry_info("kept").unwrap().is_some());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_put_async() {
+        let local_cache = TempCache::new("put_async");
+        let dir = local_cache.dir();
+        let source = dir.join("image.png");
+        fs::write(&source, b"png").unwrap();
+
+        let p1 = local_cache.put_bytes_async("generated", b"bytes", &PutOptions::new()).await.unwrap();
+        let p2 = local_cache.put_file_async("image", &source, &PutOptions::new().with_content_type("image/png")).await.unwrap();
+
+        assert_eq!(local_cache.get_local_file_path_with_name_async("http://127.0.0.1:9/generated", "generated").await.unwrap(), p1);
+        assert_eq!(fs::read(&p1).unwrap(), b"bytes");
+        assert_eq!(fs::read(&p2).unwrap(), b"png");
+        assert_eq!(local_cache.entry_info("image").unwrap().unwrap().content_type.as_deref(), Some("image/png"));
+    }
+}
@@ -0,0 +1,227 @@
+use std::error::Error;
+
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+pub use reqwest::Method;
+use serde::Serialize;
+use sha3::{Digest, Sha3_256};
+
+use crate::metadata::hex_digest;
+
+///Description of the HTTP request sent to fetch a resource: method, headers, query parameters and body.
+///Set it with FetchOptions::with_request to cache API responses that need e.g. a POST with a JSON body.
+///
+///The cache key is the URL or name given to the BTCache functions, extended with the method (other than GET),
+///the query parameters and, when requested with with_key_headers/with_key_body, the listed header values and the body.
+///Use cache_key to obtain the key of an entry (e.g. for entry_info or invalidate_cache).
+#[derive(Debug, Clone, Default)]
+pub struct FetchRequest {
+    pub(crate) method: Method,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) query: Vec<(String, String)>,
+    pub(crate) body: Option<Vec<u8>>,
+    key_headers: Vec<String>,
+    key_body: bool,
+}
+
+impl FetchRequest {
+    ///Constructor
+    ///
+    ///#Parameters
+    /// * method: HTTP method, e.g. Method::GET or Method::POST
+    pub fn new(method: Method) -> Self {
+        Self { method, ..Self::default() }
+    }
+
+    ///GET request
+    pub fn get() -> Self {
+        Self::new(Method::GET)
+    }
+
+    ///POST request
+    pub fn post() -> Self {
+        Self::new(Method::POST)
+    }
+
+    ///Request header (e.g. Accept or a tenant header)
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    ///Query parameter appended to the URL. Query parameters are always part of the cache key
+    pub fn with_query(mut self, name: &str, value: &str) -> Self {
+        self.query.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    ///Request body
+    pub fn with_body(mut self, body: &[u8]) -> Self {
+        self.body = Some(body.to_vec());
+        self
+    }
+
+    ///JSON request body. Sets the Content-Type header to application/json
+    ///
+    ///#Parameters
+    /// * value: Value serialized as the body
+    ///
+    /// #Returns
+    /// Result<FetchRequest, Box<dyn Error>>
+    ///     * Success: Ok(FetchRequest) - The request with the JSON body
+    ///     * Error: Err(Box<dyn Error>) - The value cannot be serialized
+    pub fn with_json<T: Serialize>(self, value: &T) -> Result<Self, Box<dyn Error>> {
+        let body = serde_json::to_vec(value)?;
+        Ok(self.with_header("Content-Type", "application/json").with_body(&body))
+    }
+
+    ///Fold the values of these request headers into the cache key (case insensitive names). The values are folded as their
+    ///SHA3-256 digest, so credentials sent in a key header do not appear in the key (e.g. in list_entries)
+    pub fn with_key_headers(mut self, names: &[&str]) -> Self {
+        self.key_headers.extend(names.iter().map(|n| n.to_ascii_lowercase()));
+        self
+    }
+
+    ///Fold the body into the cache key (its SHA3-256 digest), so every body gets its own entry
+    pub fn with_key_body(mut self, key_body: bool) -> Self {
+        self.key_body = key_body;
+        self
+    }
+
+    ///Cache key of the entry fetched with this request
+    ///
+    ///#Parameters
+    /// * url_name_id: URL or name given to the BTCache functions
+    ///
+    /// #Returns
+    ///     * String: url_name_id unchanged for a plain GET, otherwise url_name_id followed by the request parts folded into the key.
+    ///       Query names and values are percent-encoded, so a value containing & or = cannot collide with other parameters
+    pub fn cache_key(&self, url_name_id: &str) -> String {
+        let mut parts = Vec::new();
+        if self.method != Method::GET {
+            parts.push(format!("method={}", self.method));
+        }
+        if !self.query.is_empty() {
+            let query: Vec<String> = self.query.iter().map(|(n, v)| format!("{}={}", encode(n), encode(v))).collect();
+            parts.push(format!("query={}", query.join("&")));
+        }
+        for name in &self.key_headers {
+            //Missing headers are told apart from empty ones: a digest is never "-"
+            let value = self.header(name).map_or_else(|| "-".to_owned(), |v| digest(v.as_bytes()));
+            parts.push(format!("header:{}={}", encode(name), value));
+        }
+        if self.key_body {
+            parts.push(format!("body={}", digest(self.body.as_deref().unwrap_or_default())));
+        }
+        if parts.is_empty() {
+            return url_name_id.to_owned();
+        }
+        format!("{}\n{}", url_name_id, parts.join("\n"))
+    }
+
+    //Value of a request header
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+//Percent-encoded part of the cache key
+fn encode(part: &str) -> String {
+    utf8_percent_encode(part, NON_ALPHANUMERIC).to_string()
+}
+
+//Hex encoded SHA3-256 digest of a part of the cache key
+fn digest(data: &[u8]) -> String {
+    hex_digest(Sha3_256::new_with_prefix(data))
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod request_tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        options::FetchOptions,
+        test_server::{TestResponse, TestServer},
+        test_support::TempCache,
+    };
+
+    fn echo_server() -> TestServer {
+        TestServer::start(|req| {
+            let body = format!(
+                "{} {} tenant={} body={}",
+                req.method,
+                req.target,
+                req.header("X-Tenant").unwrap_or("-"),
+                String::from_utf8_lossy(&req.body)
+            );
+            TestResponse::ok(body.as_bytes())
+        })
+    }
+
+    #[test]
+    fn test_cache_key() {
+        assert_eq!(FetchRequest::get().with_header("Accept", "text/csv").cache_key("report"), "report");
+        assert_eq!(FetchRequest::post().with_query("y", "2024").cache_key("report"), "report\nmethod=POST\nquery=y=2024");
+        let keyed = FetchRequest::get().with_header("X-Tenant", "a").with_key_headers(&["x-tenant", "Accept"]);
+        assert_eq!(keyed.cache_key("r"), format!("r\nheader:x%2Dtenant={}\nheader:accept=-", digest(b"a")));
+        let empty = FetchRequest::get().with_header("Accept", "").with_key_headers(&["Accept"]);
+        assert_ne!(empty.cache_key("r"), FetchRequest::get().with_key_headers(&["Accept"]).cache_key("r"));
+        let secret = FetchRequest::get().with_header("Authorization", "Bearer secret").with_key_headers(&["Authorization"]);
+        assert!(!secret.cache_key("r").contains("secret"));
+        assert_ne!(FetchRequest::get().with_query("a", "1&b=2").cache_key("r"), FetchRequest::get().with_query("a", "1").with_query("b", "2").cache_key("r"));
+        assert_ne!(FetchRequest::get().with_query("a=1", "").cache_key("r"), FetchRequest::get().with_query("a", "1=").cache_key("r"));
+        let b1 = FetchRequest::post().with_body(b"1").with_key_body(true).cache_key("r");
+        let b2 = FetchRequest::post().with_body(b"2").with_key_body(true).cache_key("r");
+        assert_ne!(b1, b2);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_post_with_json_body() {
+        let server = echo_server();
+        let local_cache = TempCache::new("request");
+        let url = server.url("/reports");
+        let request = |tenant: &str, year: u32| {
+            FetchRequest::post()
+                .with_header("X-Tenant", tenant)
+                .with_query("format", "csv")
+                .with_json(&serde_json::json!({ "year": year }))
+                .unwrap()
+                .with_key_headers(&["X-Tenant"])
+                .with_key_body(true)
+        };
+        let fetch = |r: FetchRequest| {
+            let p = local_cache.get_local_file_path_with_options(&url, &url, &FetchOptions::new().with_request(r)).unwrap();
+            fs::read_to_string(p).unwrap()
+        };
+
+        assert_eq!(fetch(request("a", 2023)), "POST /reports?format=csv tenant=a body={\"year\":2023}");
+        assert_eq!(fetch(request("b", 2023)), "POST /reports?format=csv tenant=b body={\"year\":2023}");
+        assert_eq!(fetch(request("a", 2024)), "POST /reports?format=csv tenant=a body={\"year\":2024}");
+        assert_eq!(fetch(request("a", 2023)), "POST /reports?format=csv tenant=a body={\"year\":2023}");
+        assert_eq!(server.request_count(), 3);
+
+        let key = request("b", 2023).cache_key(&url);
+        assert_eq!(local_cache.entry_info(&key).unwrap().unwrap().key, key);
+        assert!(local_cache.entry_info(&url).unwrap().is_none());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_refresh_with_request_async() {
+        let server = echo_server();
+        let local_cache = TempCache::new("request_async");
+        let url = server.url("/put");
+        let options = FetchOptions::new().with_request(FetchRequest::new(Method::PUT).with_body(b"x"));
+
+        let p1 = local_cache.get_local_file_path_with_options_async(&url, "put", &options).await.unwrap();
+        let p2 = local_cache.refresh_cache_with_options_async(&url, "put", &options).await.unwrap();
+
+        assert_eq!(p1, p2);
+        assert_eq!(fs::read_to_string(p2).unwrap(), "PUT /put tenant=- body=x");
+        assert_eq!(server.request_count(), 2);
+    }
+}
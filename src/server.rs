@@ -0,0 +1,312 @@
+//Local HTTP server exposing a BTCache to other processes (feature "server").
+//Every connection is handled on its own thread and closed after a single response.
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::Path,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use bt_logger::{get_error, log_error, log_verbose};
+use reqwest::Url;
+use sha3::{Digest, Sha3_256};
+
+use crate::{cache::BTCache, metadata::{EntryMetadata, hex_digest}, options::FetchOptions};
+
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+//Client headers forwarded to the origin server, they select the cached variant when the response has a Vary header
+const FORWARDED_HEADERS: [&str; 2] = ["accept", "accept-language"];
+
+///HTTP front end for a BTCache. Files are served from the cache and downloaded on a miss.
+///
+///Supported requests (GET and HEAD):
+///     * `/fetch?url=<url>[&name=<name>]`: returns the cached copy of url, stored under name when given
+///     * Forward-proxy style, e.g. `GET http://host/file HTTP/1.1` with the server configured as HTTP proxy
+///
+///An `Authorization: Bearer <token>` request header is used as access token for the download. Responses fetched with a
+///token are private to that token (RFC 9111 section 3.5): the digest of the token is folded into the cache name, so they are
+///never served to clients sending another token or none. Accept and Accept-Language are forwarded to the origin server.
+///Responses carry Content-Type, Content-Length, ETag and Last-Modified (when known) and
+///`If-None-Match` requests matching the ETag are answered with 304 Not Modified.
+pub struct CacheServer {
+    cache: Arc<BTCache>,
+    listener: TcpListener,
+}
+
+impl CacheServer {
+    ///Bind a server for the cache to a local address. Use port 0 to let the system pick a free port.
+    ///
+    ///#Parameters
+    /// * cache: Cache used to serve the requests
+    /// * addr: Address to listen on, e.g. "127.0.0.1:8080"
+    ///
+    /// #Returns
+    /// Result<CacheServer, Box<dyn Error>>
+    ///     * Success: Ok(CacheServer) - Server ready to run
+    ///     * Error: Err(Box<dyn Error>) - The address cannot be bound
+    pub fn bind<A: ToSocketAddrs>(cache: Arc<BTCache>, addr: A) -> Result<Self, Box<dyn Error>> {
+        let listener = TcpListener::bind(addr).map_err(|e| get_error!("CacheServer::bind", "Unable to bind server: {}", e))?;
+        Ok(Self { cache, listener })
+    }
+
+    ///Address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    ///Serve requests until the process ends. Blocks the current thread.
+    pub fn run(self) {
+        for stream in self.listener.incoming() {
+            match stream {
+                Ok(s) => {
+                    let cache = self.cache.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = Self::serve(&cache, s) {
+                            log_error!("CacheServer::run", "Connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => log_error!("CacheServer::run", "Unable to accept connection: {}", e),
+            }
+        }
+    }
+
+    ///Serve requests on a background thread.
+    pub fn spawn(self) -> JoinHandle<()> {
+        thread::spawn(move || self.run())
+    }
+
+    fn serve(cache: &BTCache, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or("").to_owned();
+        let target = parts.next().unwrap_or("").to_owned();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut h = String::new();
+            if reader.read_line(&mut h)? == 0 || h.trim().is_empty() {
+                break;
+            }
+            if let Some((n, v)) = h.split_once(':') {
+                headers.push((n.trim().to_ascii_lowercase(), v.trim().to_owned()));
+            }
+        }
+        let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+        log_verbose!("CacheServer::serve", "{} {}", method, target);
+
+        let mut out = stream;
+        if method != "GET" && method != "HEAD" {
+            return Self::write_error(&mut out, 405, "Method Not Allowed", "Only GET and HEAD are supported");
+        }
+        let (url, name) = match Self::parse_target(&target) {
+            Ok(t) => t,
+            Err((status, reason, message)) => return Self::write_error(&mut out, status, reason, &message),
+        };
+
+        let mut options = FetchOptions::new();
+        let token = header("authorization").and_then(|a| a.strip_prefix("Bearer "));
+        if let Some(token) = token {
+            options = options.with_token(token);
+        }
+        let name = Self::private_name(&name, token);
+        for name in FORWARDED_HEADERS {
+            if let Some(value) = header(name) {
+                options = options.with_header(name, value);
+            }
+        }
+        let path = match cache.get_local_file_path_with_options(&url, &name, &options) {
+            Ok(p) => p,
+            Err(e) => return Self::write_error(&mut out, 502, "Bad Gateway", &format!("Unable to fetch '{}': {}", url, e)),
+        };
+
+        let metadata = EntryMetadata::load(Path::new(&path)).ok();
+        let etag = metadata.as_ref().and_then(|m| m.etag.clone().or_else(|| m.digest.as_ref().map(|d| format!("\"{}\"", d))));
+        if let (Some(etag), Some(inm)) = (&etag, header("if-none-match"))
+            && inm.split(',').any(|t| t.trim() == etag || t.trim() == "*")
+        {
+            return Self::write_head(&mut out, 304, "Not Modified", &[("ETag", etag.as_str())], None);
+        }
+
+        let mut file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        let content_type = metadata.as_ref().and_then(|m| m.content_type.clone()).unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_owned());
+        let mut response_headers = vec![("Content-Type", content_type.as_str())];
+        if let Some(e) = &etag {
+            response_headers.push(("ETag", e));
+        }
+        if let Some(lm) = metadata.as_ref().and_then(|m| m.last_modified.as_deref()) {
+            response_headers.push(("Last-Modified", lm));
+        }
+        Self::write_head(&mut out, 200, "OK", &response_headers, Some(size))?;
+        if method == "GET" {
+            io::copy(&mut file, &mut out)?;
+        }
+        out.flush()
+    }
+
+    //Returns the URL to fetch and the cache name, or the status to answer with
+    fn parse_target(target: &str) -> Result<(String, String), (u16, &'static str, String)> {
+        if target.starts_with("http://") || target.starts_with("https://") {
+            return Ok((target.to_owned(), target.to_owned()));
+        }
+        let parsed = Url::parse("http://localhost")
+            .and_then(|base| base.join(target))
+            .map_err(|e| (400, "Bad Request", format!("Invalid request target '{}': {}", target, e)))?;
+        if parsed.path() != "/fetch" {
+            return Err((404, "Not Found", format!("Unknown path '{}'. Use /fetch?url=<url>", parsed.path())));
+        }
+        let param = |key: &str| parsed.query_pairs().find(|(k, _)| k == key).map(|(_, v)| v.into_owned());
+        let url = param("url").ok_or((400, "Bad Request", "Missing 'url' query parameter".to_owned()))?;
+        //Only remote resources are served: file:// or data: URLs would expose the files of the host
+        let scheme = url.split_once(':').map(|(s, _)| s.to_ascii_lowercase());
+        if !matches!(scheme.as_deref(), Some("http" | "https")) {
+            return Err((400, "Bad Request", format!("Unsupported URL '{}'. Only http and https URLs are fetched", url)));
+        }
+        let name = param("name").unwrap_or_else(|| url.clone());
+        Ok((url, name))
+    }
+
+    //Cache name of a request. Responses fetched with a token are stored under the name followed by the digest of the token
+    fn private_name(name: &str, token: Option<&str>) -> String {
+        match token {
+            Some(t) => format!("{}\nauth={}", name, hex_digest(Sha3_256::new_with_prefix(t))),
+            None => name.to_owned(),
+        }
+    }
+
+    fn write_head(out: &mut TcpStream, status: u16, reason: &str, headers: &[(&str, &str)], length: Option<u64>) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\nConnection: close\r\n", status, reason);
+        for (n, v) in headers {
+            head.push_str(&format!("{}: {}\r\n", n, v));
+        }
+        if let Some(l) = length {
+            head.push_str(&format!("Content-Length: {}\r\n", l));
+        }
+        head.push_str("\r\n");
+        out.write_all(head.as_bytes())
+    }
+
+    fn write_error(out: &mut TcpStream, status: u16, reason: &str, message: &str) -> io::Result<()> {
+        log_error!("CacheServer::serve", "{} {}: {}", status, reason, message);
+        Self::write_head(out, status, reason, &[("Content-Type", "text/plain; charset=utf-8")], Some(message.len() as u64))?;
+        out.write_all(message.as_bytes())?;
+        out.flush()
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod server_tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::{test_server::{TestResponse, TestServer}, test_support::TempDir};
+
+    //Address of a running server and the temporary folder of its cache, removed when the test ends
+    fn start_proxy() -> (SocketAddr, TempDir) {
+        let dir = TempDir::new("server");
+        let server = CacheServer::bind(Arc::new(BTCache::with_dir(dir.join("cache")).unwrap()), "127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        server.spawn();
+        (addr, dir)
+    }
+
+    fn raw_request(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_fetch_served_from_cache() {
+        let upstream = TestServer::start(|_| {
+            TestResponse::ok(b"{\"cached\":true}").header("Content-Type", "application/json").header("ETag", "\"srv1\"")
+        });
+        let (addr, _dir) = start_proxy();
+        let url = upstream.url("/server_fetch.json");
+        let client = reqwest::blocking::Client::new();
+        let proxy_url = Url::parse_with_params(&format!("http://{}/fetch", addr), &[("url", url.as_str())]).unwrap();
+
+        let first = client.get(proxy_url.clone()).send().unwrap();
+        assert_eq!(first.status(), 200);
+        assert_eq!(first.headers()["content-type"], "application/json");
+        assert_eq!(first.headers()["etag"], "\"srv1\"");
+        assert_eq!(first.headers()["content-length"], "15");
+        assert_eq!(first.text().unwrap(), "{\"cached\":true}");
+
+        let second = client.get(proxy_url.clone()).send().unwrap();
+        assert_eq!(second.text().unwrap(), "{\"cached\":true}");
+        assert_eq!(upstream.request_count(), 1);
+
+        let not_modified = client.get(proxy_url).header("If-None-Match", "\"srv1\"").send().unwrap();
+        assert_eq!(not_modified.status(), 304);
+    }
+
+    #[test]
+    fn test_authenticated_response_not_shared() {
+        let upstream = TestServer::start(|req| TestResponse::ok(req.header("Authorization").unwrap_or("anonymous").as_bytes()));
+        let (addr, _dir) = start_proxy();
+        let url = upstream.url("/server_private");
+        let client = reqwest::blocking::Client::new();
+        let proxy_url = Url::parse_with_params(&format!("http://{}/fetch", addr), &[("url", url.as_str())]).unwrap();
+        let fetch = |token: Option<&str>| {
+            let request = client.get(proxy_url.clone());
+            let request = match token {
+                Some(t) => request.bearer_auth(t),
+                None => request,
+            };
+            request.send().unwrap().text().unwrap()
+        };
+
+        assert_eq!(fetch(Some("secret")), "Bearer secret");
+        assert_eq!(fetch(None), "anonymous");
+        assert_eq!(fetch(Some("other")), "Bearer other");
+        assert_eq!(fetch(Some("secret")), "Bearer secret");
+        assert_eq!(fetch(None), "anonymous");
+        assert_eq!(upstream.request_count(), 3);
+    }
+
+    #[test]
+    fn test_forward_proxy_request() {
+        let upstream = TestServer::start(|_| TestResponse::ok(b"proxied"));
+        let (addr, _dir) = start_proxy();
+        let url = upstream.url("/server_forward");
+
+        let response = raw_request(addr, &format!("GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", url));
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: application/octet-stream"));
+        assert!(response.contains("Content-Length: 7"));
+        assert!(response.ends_with("\r\n\r\nproxied"));
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let (addr, _dir) = start_proxy();
+
+        assert!(raw_request(addr, "GET /fetch HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 400"));
+        assert!(raw_request(addr, "GET /other HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert!(raw_request(addr, "DELETE /fetch?url=x HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+        assert!(raw_request(addr, "GET /fetch?url=http%3A%2F%2F127.0.0.1%3A1%2Fnone HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 502"));
+    }
+
+    #[test]
+    fn test_local_sources_refused() {
+        let (addr, _dir) = start_proxy();
+
+        assert!(raw_request(addr, "GET /fetch?url=file%3A%2F%2F%2Fetc%2Fpasswd HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 400"));
+        assert!(raw_request(addr, "GET /fetch?url=FILE:///etc/passwd HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 400"));
+        assert!(raw_request(addr, "GET /fetch?url=data%3A%2Chello HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 400"));
+        assert!(raw_request(addr, "GET /fetch?url=relative HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 400"));
+    }
+}
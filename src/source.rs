@@ -0,0 +1,139 @@
+//Sources that are not fetched over HTTP: local files (file:// URLs) and inline data (data: URIs).
+//They go through the same get/refresh API and cache keys as the HTTP URLs (see Downloader::ingest_local),
+//once enabled with FetchOptions::with_local_sources.
+use std::{error::Error, path::PathBuf};
+
+use base64::{Engine, engine::general_purpose};
+use bt_logger::get_error;
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+
+//Media type of a data: URI that does not specify one (RFC 2397)
+const DEFAULT_DATA_TYPE: &str = "text/plain;charset=US-ASCII";
+
+//Content of a file:// URL or a data: URI
+#[derive(Debug, PartialEq)]
+pub(crate) enum LocalSource {
+    File(PathBuf),
+    Data { content_type: String, data: Vec<u8> },
+}
+
+impl LocalSource {
+    //Local source of url, None for the URLs fetched over HTTP
+    pub(crate) fn parse(url: &str) -> Result<Option<Self>, Box<dyn Error>> {
+        let Some((scheme, rest)) = url.split_once(':') else {
+            return Ok(None)
+        };
+        if scheme.eq_ignore_ascii_case("file") {
+            let path = Url::parse(url)?
+                .to_file_path()
+                .map_err(|_| get_error!("LocalSource::parse","Invalid file URL: {}", url))?;
+            return Ok(Some(Self::File(path)))
+        }
+        if scheme.eq_ignore_ascii_case("data") {
+            return Self::parse_data(rest).map(Some)
+        }
+        Ok(None)
+    }
+
+    //Local source of url when local sources are allowed. file:// and data: URLs fail otherwise
+    pub(crate) fn parse_allowed(url: &str, allowed: bool) -> Result<Option<Self>, Box<dyn Error>> {
+        match Self::parse(url)? {
+            Some(_) if !allowed => {
+                let scheme = url.split(':').next().unwrap_or_default();
+                Err(get_error!("LocalSource::parse_allowed","'{}' sources are disabled. Enable them with FetchOptions::with_local_sources", scheme).into())
+            },
+            source => Ok(source),
+        }
+    }
+
+    //data:[<media type>][;base64],<data>
+    fn parse_data(rest: &str) -> Result<Self, Box<dyn Error>> {
+        let Some((header, payload)) = rest.split_once(',') else {
+            return Err(get_error!("LocalSource::parse_data","Invalid data URI: missing ','").into())
+        };
+        let decoded: Vec<u8> = percent_decode_str(payload).collect();
+        let (media_type, data) = match header.len().checked_sub(";base64".len()).filter(|&i| header[i..].eq_ignore_ascii_case(";base64")) {
+            Some(i) => (&header[..i], general_purpose::STANDARD.decode(&decoded)?),
+            None => (header, decoded),
+        };
+        let content_type = if media_type.is_empty() { DEFAULT_DATA_TYPE.to_owned() } else { media_type.to_owned() };
+        Ok(Self::Data { content_type, data })
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod source_tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{options::FetchOptions, test_support::TempCache};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(LocalSource::parse("https://example.com/a").unwrap(), None);
+        assert_eq!(LocalSource::parse("report").unwrap(), None);
+        assert_eq!(LocalSource::parse("file:///tmp/a%20b.txt").unwrap(), Some(LocalSource::File(PathBuf::from("/tmp/a b.txt"))));
+        assert_eq!(
+            LocalSource::parse("data:,Hello%2C%20World").unwrap(),
+            Some(LocalSource::Data { content_type: DEFAULT_DATA_TYPE.to_owned(), data: b"Hello, World".to_vec() })
+        );
+        assert_eq!(
+            LocalSource::parse("data:application/json;BASE64,eyJhIjoxfQ==").unwrap(),
+            Some(LocalSource::Data { content_type: "application/json".to_owned(), data: b"{\"a\":1}".to_vec() })
+        );
+        assert!(LocalSource::parse("data:text/plain").is_err());
+        assert!(LocalSource::parse("data:;base64,***").is_err());
+        assert!(LocalSource::parse_allowed("file:///etc/passwd", false).is_err());
+        assert!(LocalSource::parse_allowed("DATA:,x", false).is_err());
+        assert_eq!(LocalSource::parse_allowed("https://example.com/a", false).unwrap(), None);
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_file_and_data_sources() {
+        let local_cache = TempCache::new("source");
+        let dir = local_cache.dir();
+        let source = dir.join("config.toml");
+        fs::write(&source, "v1").unwrap();
+        let url = Url::from_file_path(&source).unwrap().to_string();
+        let options = FetchOptions::new().with_local_sources(true);
+
+        //Local sources are refused unless enabled
+        assert!(local_cache.get_local_file_path(&url).is_err());
+        assert!(local_cache.get_file_data_base64("data:text/plain,inline").is_err());
+        assert_eq!(local_cache.file_count(), 0);
+
+        let p = local_cache.get_local_file_path_with_options(&url, &url, &options).unwrap();
+        assert_eq!(fs::read(&p).unwrap(), b"v1");
+        fs::write(&source, "v2").unwrap();
+        assert_eq!(fs::read(local_cache.get_local_file_path_with_options(&url, &url, &options).unwrap()).unwrap(), b"v1");
+        assert_eq!(fs::read(local_cache.refresh_cache_with_options(&url, &url, &options).unwrap()).unwrap(), b"v2");
+        assert_eq!(local_cache.entry_info(&url).unwrap().unwrap().size, 2);
+
+        let linked = local_cache.get_local_file_path_with_options(&url, "linked", &options.clone().with_hardlink(true)).unwrap();
+        assert_eq!(fs::read(&linked).unwrap(), b"v2");
+        let missing = format!("{}.missing", url);
+        assert!(local_cache.get_local_file_path_with_options(&missing, &missing, &options).is_err());
+
+        let data = local_cache.get_file_data_base64_with_options("data:text/plain,inline", "data:text/plain,inline", &options).unwrap();
+        assert_eq!(data, general_purpose::STANDARD.encode("inline"));
+        assert_eq!(local_cache.entry_info("data:text/plain,inline").unwrap().unwrap().content_type.as_deref(), Some("text/plain"));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_data_source_async() {
+        let local_cache = TempCache::new("source_async");
+
+        let options = FetchOptions::new().with_local_sources(true);
+        assert!(local_cache.get_local_file_path_with_name_async("data:;base64,Ynl0ZXM=", "inline").await.is_err());
+
+        let p = local_cache.get_local_file_path_with_options_async("data:;base64,Ynl0ZXM=", "inline", &options).await.unwrap();
+
+        assert_eq!(fs::read(p).unwrap(), b"bytes");
+    }
+}
@@ -0,0 +1,325 @@
+use std::sync::{
+    Arc, RwLock,
+    atomic::{AtomicU64, Ordering},
+};
+
+///Snapshot of the activity of a BTCache instance, returned by BTCache::stats.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    ///hits: Requests served from a file already in the cache
+    pub hits: u64,
+    ///misses: Requests for a file not in the cache
+    pub misses: u64,
+    ///downloads: Files downloaded (misses and revalidations that returned new content)
+    pub downloads: u64,
+    ///bytes_downloaded: Bytes written to the cache by downloads
+    pub bytes_downloaded: u64,
+    ///bytes_served: Size of the files returned to the callers
+    pub bytes_served: u64,
+    ///evictions: Entries removed from the cache
+    pub evictions: u64,
+    ///revalidations_not_modified: Refreshes answered with 304 Not Modified (cached file kept)
+    pub revalidations_not_modified: u64,
+    ///revalidations_modified: Refreshes answered with new content (200)
+    pub revalidations_modified: u64,
+    ///errors: Requests that failed
+    pub errors: u64,
+}
+
+impl CacheStats {
+    ///Ratio of hits over all requests (hits + misses). Zero when nothing was requested.
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 { 0.0 } else { self.hits as f64 / total as f64 }
+    }
+}
+
+///Receives every counter update of a BTCache instance. Register it with BTCache::set_metrics_recorder
+///to publish the statistics to a metrics system.
+///With the "metrics" feature the counters are also published through the metrics crate facade (`metrics::counter!`)
+///to the recorder installed by the application. The counters of all instances add up; the hit ratio is computed by the
+///dashboards from bt_file_cache_hits and bt_file_cache_misses.
+///
+///Counter names: bt_file_cache_hits, bt_file_cache_misses, bt_file_cache_downloads, bt_file_cache_bytes_downloaded,
+///bt_file_cache_bytes_served, bt_file_cache_evictions, bt_file_cache_revalidations_not_modified,
+///bt_file_cache_revalidations_modified, bt_file_cache_errors
+pub trait MetricsRecorder: Send + Sync {
+    fn increment_counter(&self, name: &'static str, value: u64);
+}
+
+//Counters kept by each BTCache instance
+#[derive(Default)]
+pub(crate) struct StatsCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    downloads: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    bytes_served: AtomicU64,
+    evictions: AtomicU64,
+    revalidations_not_modified: AtomicU64,
+    revalidations_modified: AtomicU64,
+    errors: AtomicU64,
+    recorder: RwLock<Option<Arc<dyn MetricsRecorder>>>,
+}
+
+impl StatsCounters {
+    fn add(&self, counter: &AtomicU64, name: &'static str, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+        if let Ok(r) = self.recorder.read()
+            && let Some(recorder) = r.as_ref()
+        {
+            recorder.increment_counter(name, value);
+        }
+        #[cfg(feature = "metrics")]
+        metrics::counter!(name).increment(value);
+    }
+
+    pub(crate) fn record_hit(&self, size: u64) {
+        self.add(&self.hits, "bt_file_cache_hits", 1);
+        self.record_served(size);
+    }
+
+    pub(crate) fn record_miss(&self) {
+        self.add(&self.misses, "bt_file_cache_misses", 1);
+    }
+
+    pub(crate) fn record_download(&self, size: u64) {
+        self.add(&self.downloads, "bt_file_cache_downloads", 1);
+        self.add(&self.bytes_downloaded, "bt_file_cache_bytes_downloaded", size);
+    }
+
+    pub(crate) fn record_served(&self, size: u64) {
+        self.add(&self.bytes_served, "bt_file_cache_bytes_served", size);
+    }
+
+    pub(crate) fn record_eviction(&self) {
+        self.add(&self.evictions, "bt_file_cache_evictions", 1);
+    }
+
+    pub(crate) fn record_revalidation(&self, modified: bool) {
+        if modified {
+            self.add(&self.revalidations_modified, "bt_file_cache_revalidations_modified", 1);
+        } else {
+            self.add(&self.revalidations_not_modified, "bt_file_cache_revalidations_not_modified", 1);
+        }
+    }
+
+    pub(crate) fn record_error(&self) {
+        self.add(&self.errors, "bt_file_cache_errors", 1);
+    }
+
+    pub(crate) fn set_recorder(&self, recorder: Option<Arc<dyn MetricsRecorder>>) {
+        if let Ok(mut r) = self.recorder.write() {
+            *r = recorder;
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> CacheStats {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        CacheStats {
+            hits: get(&self.hits),
+            misses: get(&self.misses),
+            downloads: get(&self.downloads),
+            bytes_downloaded: get(&self.bytes_downloaded),
+            bytes_served: get(&self.bytes_served),
+            evictions: get(&self.evictions),
+            revalidations_not_modified: get(&self.revalidations_not_modified),
+            revalidations_modified: get(&self.revalidations_modified),
+            errors: get(&self.errors),
+        }
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod stats_tests {
+    #[cfg(feature = "blocking")]
+    use std::{
+        collections::HashMap,
+        sync::{
+            Mutex,
+            atomic::AtomicUsize,
+        },
+    };
+
+    #[cfg(feature = "blocking")]
+    use super::*;
+    use crate::{
+        test_server::{TestResponse, TestServer},
+        test_support::TempCache,
+    };
+
+    #[cfg(feature = "blocking")]
+    #[derive(Default)]
+    struct TestRecorder {
+        counters: Mutex<HashMap<&'static str, u64>>,
+    }
+
+    #[cfg(feature = "blocking")]
+    impl MetricsRecorder for TestRecorder {
+        fn increment_counter(&self, name: &'static str, value: u64) {
+            *self.counters.lock().unwrap().entry(name).or_default() += value;
+        }
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_hits_misses_and_errors() {
+        let server = TestServer::start(|req| match req.path() {
+            "/stats_missing" => TestResponse::status(404, b""),
+            _ => TestResponse::ok(b"0123456789"),
+        });
+        let local_cache = TempCache::new("stats");
+        let recorder = Arc::new(TestRecorder::default());
+        local_cache.set_metrics_recorder(Some(recorder.clone()));
+        let url = server.url("/stats_file");
+
+        local_cache.get_local_file_path(&url).unwrap();
+        local_cache.get_local_file_path(&url).unwrap();
+        assert!(local_cache.get_local_file_path(&server.url("/stats_missing")).is_err());
+        local_cache.invalidate_cache(&url).unwrap();
+
+        let stats = local_cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.downloads, 1);
+        assert_eq!(stats.bytes_downloaded, 10);
+        assert_eq!(stats.bytes_served, 20);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.evictions, 1);
+        assert!((stats.hit_ratio() - 1.0 / 3.0).abs() < f64::EPSILON);
+
+        let counters = recorder.counters.lock().unwrap();
+        assert_eq!(counters.get("bt_file_cache_hits"), Some(&1));
+        assert_eq!(counters.get("bt_file_cache_bytes_served"), Some(&20));
+        assert_eq!(counters.get("bt_file_cache_errors"), Some(&1));
+    }
+
+    //Recorder of the metrics crate facade keeping the value of every counter
+    #[cfg(all(feature = "metrics", feature = "blocking"))]
+    #[derive(Default)]
+    struct FacadeRecorder {
+        values: Arc<Mutex<HashMap<String, f64>>>,
+    }
+
+    #[cfg(all(feature = "metrics", feature = "blocking"))]
+    struct FacadeHandle {
+        name: String,
+        values: Arc<Mutex<HashMap<String, f64>>>,
+    }
+
+    #[cfg(all(feature = "metrics", feature = "blocking"))]
+    impl FacadeHandle {
+        fn update(&self, f: impl FnOnce(f64) -> f64) {
+            let mut values = self.values.lock().unwrap();
+            let value = values.entry(self.name.clone()).or_default();
+            *value = f(*value);
+        }
+    }
+
+    #[cfg(all(feature = "metrics", feature = "blocking"))]
+    impl metrics::CounterFn for FacadeHandle {
+        fn increment(&self, value: u64) {
+            self.update(|v| v + value as f64);
+        }
+        fn absolute(&self, value: u64) {
+            self.update(|v| v.max(value as f64));
+        }
+    }
+
+    #[cfg(all(feature = "metrics", feature = "blocking"))]
+    impl metrics::Recorder for FacadeRecorder {
+        fn describe_counter(&self, _: metrics::KeyName, _: Option<metrics::Unit>, _: metrics::SharedString) {}
+        fn describe_gauge(&self, _: metrics::KeyName, _: Option<metrics::Unit>, _: metrics::SharedString) {}
+        fn describe_histogram(&self, _: metrics::KeyName, _: Option<metrics::Unit>, _: metrics::SharedString) {}
+        fn register_counter(&self, key: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Counter {
+            metrics::Counter::from_arc(Arc::new(FacadeHandle { name: key.name().to_owned(), values: self.values.clone() }))
+        }
+        fn register_gauge(&self, key: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Gauge {
+            self.values.lock().unwrap().insert(key.name().to_owned(), f64::NAN);
+            metrics::Gauge::noop()
+        }
+        fn register_histogram(&self, _: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Histogram {
+            metrics::Histogram::noop()
+        }
+    }
+
+    #[cfg(all(feature = "metrics", feature = "blocking"))]
+    #[test]
+    fn test_metrics_facade() {
+        let server = TestServer::start(|req| match req.path() {
+            "/facade_missing" => TestResponse::status(404, b""),
+            _ => TestResponse::ok(b"0123456789"),
+        });
+        let local_cache = TempCache::new("stats_facade");
+        let recorder = FacadeRecorder::default();
+        let url = server.url("/facade_file");
+
+        metrics::with_local_recorder(&recorder, || {
+            local_cache.get_local_file_path(&url).unwrap();
+            local_cache.get_local_file_path(&url).unwrap();
+            assert!(local_cache.get_local_file_path(&server.url("/facade_missing")).is_err());
+        });
+
+        let values = recorder.values.lock().unwrap();
+        let value = |name: &str| values.get(name).copied();
+        assert_eq!(value("bt_file_cache_hits"), Some(1.0));
+        assert_eq!(value("bt_file_cache_misses"), Some(2.0));
+        assert_eq!(value("bt_file_cache_bytes_downloaded"), Some(10.0));
+        assert_eq!(value("bt_file_cache_bytes_served"), Some(20.0));
+        assert_eq!(value("bt_file_cache_errors"), Some(1.0));
+        //No per process gauge mixing the instances: the hit ratio comes from the counters
+        assert!(!values.keys().any(|name| name.contains("ratio")));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_revalidation_not_modified_async() {
+        let server = TestServer::start(|req| match req.header("If-None-Match") {
+            Some("\"abc\"") => TestResponse::status(304, b"").header("ETag", "\"abc\""),
+            _ => TestResponse::ok(b"original").header("ETag", "\"abc\""),
+        });
+        let local_cache = TempCache::new("stats_not_modified_async");
+        let url = server.url("/stats_revalidate");
+
+        let p = local_cache.get_local_file_path_async(&url).await.unwrap();
+        let r = local_cache.refresh_cache_async(&url).await.unwrap();
+
+        assert_eq!(p, r);
+        assert_eq!(std::fs::read(&r).unwrap(), b"original");
+        let stats = local_cache.stats();
+        assert_eq!(stats.revalidations_not_modified, 1);
+        assert_eq!(stats.revalidations_modified, 0);
+        assert_eq!(stats.downloads, 1);
+        assert_eq!(server.requests()[1].header("If-None-Match"), Some("\"abc\""));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_revalidation_modified() {
+        let version = Arc::new(AtomicUsize::new(1));
+        let th_version = version.clone();
+        let server = TestServer::start(move |req| {
+            let v = format!("\"v{}\"", th_version.load(std::sync::atomic::Ordering::SeqCst));
+            if req.header("If-None-Match") == Some(v.as_str()) {
+                TestResponse::status(304, b"")
+            } else {
+                TestResponse::ok(v.as_bytes()).header("ETag", &v)
+            }
+        });
+        let local_cache = TempCache::new("stats_modified");
+        let url = server.url("/stats_modified");
+
+        local_cache.get_local_file_path(&url).unwrap();
+        version.store(2, std::sync::atomic::Ordering::SeqCst);
+        let r = local_cache.refresh_cache(&url).unwrap();
+
+        assert_eq!(std::fs::read(&r).unwrap(), b"\"v2\"");
+        let stats = local_cache.stats();
+        assert_eq!(stats.revalidations_modified, 1);
+        assert_eq!(stats.revalidations_not_modified, 0);
+        assert_eq!(stats.downloads, 2);
+    }
+}
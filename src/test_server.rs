@@ -0,0 +1,158 @@
+//Minimal HTTP/1.1 server used by the unit tests so they do not depend on external websites.
+//Every connection is handled on its own thread and closed after a single response.
+#![allow(dead_code)]
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+///Request received by the test server
+#[derive(Debug, Clone)]
+pub(crate) struct TestRequest {
+    pub method: String,
+    ///Path including the query string
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or("")
+    }
+}
+
+///Response returned by the test handler
+pub(crate) struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    ///Pause between each chunk of the body (chunk_size bytes) to simulate slow transfers
+    pub delay: Option<(usize, Duration)>,
+}
+
+impl TestResponse {
+    pub fn ok(body: &[u8]) -> Self {
+        Self::status(200, body)
+    }
+
+    pub fn status(status: u16, body: &[u8]) -> Self {
+        Self { status, headers: Vec::new(), body: body.to_vec(), delay: None }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn slow(mut self, chunk_size: usize, pause: Duration) -> Self {
+        self.delay = Some((chunk_size, pause));
+        self
+    }
+}
+
+type Handler = dyn Fn(&TestRequest) -> TestResponse + Send + Sync;
+
+pub(crate) struct TestServer {
+    port: u16,
+    requests: Arc<Mutex<Vec<TestRequest>>>,
+}
+
+impl TestServer {
+    pub fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&TestRequest) -> TestResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Unable to bind test server");
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let th_requests = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handler = handler.clone();
+                let requests = th_requests.clone();
+                thread::spawn(move || {
+                    let _ = Self::serve(stream, handler.as_ref(), &requests);
+                });
+            }
+        });
+
+        Self { port, requests }
+    }
+
+    pub fn url(&self, target: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, target)
+    }
+
+    pub fn requests(&self) -> Vec<TestRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn request_count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    fn serve(stream: TcpStream, handler: &Handler, requests: &Mutex<Vec<TestRequest>>) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or("").to_owned();
+        let target = parts.next().unwrap_or("").to_owned();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut h = String::new();
+            if reader.read_line(&mut h)? == 0 || h.trim().is_empty() {
+                break;
+            }
+            if let Some((n, v)) = h.split_once(':') {
+                headers.push((n.trim().to_owned(), v.trim().to_owned()));
+            }
+        }
+
+        let length = headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, v)| v.parse::<usize>().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+
+        let request = TestRequest { method, target, headers, body };
+        requests.lock().unwrap().push(request.clone());
+        let response = handler(&request);
+
+        let mut out = stream;
+        let mut head = format!("HTTP/1.1 {} TEST\r\nConnection: close\r\n", response.status);
+        if !response.headers.iter().any(|(n, _)| n.eq_ignore_ascii_case("content-length")) {
+            head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+        }
+        for (n, v) in &response.headers {
+            head.push_str(&format!("{}: {}\r\n", n, v));
+        }
+        head.push_str("\r\n");
+        out.write_all(head.as_bytes())?;
+
+        match response.delay {
+            Some((chunk_size, pause)) => {
+                for chunk in response.body.chunks(chunk_size.max(1)) {
+                    out.write_all(chunk)?;
+                    out.flush()?;
+                    thread::sleep(pause);
+                }
+            }
+            None => out.write_all(&response.body)?,
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,78 @@
+//Helpers shared by the unit tests: temporary folders removed when dropped, even when an assertion panics.
+#![allow(dead_code)]
+use std::{
+    env, fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::cache::BTCache;
+
+static TEMP_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+///Temporary folder, unique per test (process id, sequence and start time), removed with its content when dropped
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+        let seq = TEMP_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("bt_file_cache_{}_{}_{}_{}", name, process::id(), seq, nanos));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+///BTCache in the "cache" subfolder of a TempDir. The rest of the TempDir is free for test files (sources, other caches)
+pub(crate) struct TempCache {
+    cache: BTCache,
+    dir: TempDir,
+}
+
+impl TempCache {
+    pub fn new(name: &str) -> Self {
+        Self::with(name, |c| c)
+    }
+
+    ///Cache configured by the given function (permissions, key strategy, credential provider, ...)
+    pub fn with<F: FnOnce(BTCache) -> BTCache>(name: &str, configure: F) -> Self {
+        let dir = TempDir::new(name);
+        let cache = configure(BTCache::with_dir(dir.join("cache")).unwrap());
+        Self { cache, dir }
+    }
+
+    ///Temporary folder holding the cache folder
+    pub fn dir(&self) -> &TempDir {
+        &self.dir
+    }
+
+    ///Number of files in the cache folder
+    pub fn file_count(&self) -> usize {
+        fs::read_dir(self.cache.folder_path()).unwrap().count()
+    }
+}
+
+impl Deref for TempCache {
+    type Target = BTCache;
+
+    fn deref(&self) -> &BTCache {
+        &self.cache
+    }
+}
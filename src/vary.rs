@@ -0,0 +1,237 @@
+//Vary support: when a response carries a Vary header, the names of the listed request headers are recorded next to the
+//entry (<hash>.vary) and the values sent with each request become part of the cache key, so every variant
+//(language, format, credentials...) gets its own cache file.
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bt_logger::log_error;
+use reqwest::header::{HeaderMap, VARY};
+
+#[cfg(feature = "async")]
+use crate::fs_io::nonblocking;
+use crate::{cache::BTCache, metadata::{EntryFilter, EntryMetadata}, options::FetchOptions};
+
+const VARY_EXTENSION: &str = "vary";
+
+//Request header names listed in the Vary header of a response, lowercased and sorted.
+//"*" (the response varies on anything) cannot be keyed, such responses are cached as a single variant
+pub(crate) fn vary_from_headers(headers: &HeaderMap) -> Vec<String> {
+    let mut names: Vec<String> = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|n| n.trim().to_ascii_lowercase())
+        .filter(|n| !n.is_empty() && n != "*")
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+pub(crate) fn vary_record_path(base_path: &Path) -> PathBuf {
+    base_path.with_extension(VARY_EXTENSION)
+}
+
+fn load_vary_record(base_path: &Path) -> Vec<String> {
+    fs::read(vary_record_path(base_path))
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
+fn save_vary_record(base_path: &Path, names: &[String]) -> Result<(), Box<dyn Error>> {
+    let path = vary_record_path(base_path);
+    if names.is_empty() {
+        let _ = fs::remove_file(path);
+        return Ok(());
+    }
+    fs::write(path, serde_json::to_vec(names)?)?;
+    Ok(())
+}
+
+//Value of a request header as sent by the fetch. Credentials sent as a header (token, basic, custom header) count too,
+//credentials supplied by a callback are not known in advance and are ignored
+fn request_header(options: &FetchOptions, name: &str) -> Option<String> {
+    options
+        .request
+        .iter()
+        .flat_map(|r| &r.headers)
+        .chain(&options.headers)
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.clone())
+        .or_else(|| options.auth.as_ref().and_then(|a| a.static_header(name)))
+}
+
+impl BTCache {
+    //Cache file of the variant selected by the request headers of the fetch
+    pub(crate) fn entry_path(&self, file_name: &str, options: &FetchOptions) -> PathBuf {
+        let base_path = self.get_file(file_name);
+        self.variant_path(&base_path, file_name, options, &load_vary_record(&base_path))
+    }
+
+    fn variant_path(&self, base_path: &Path, file_name: &str, options: &FetchOptions, names: &[String]) -> PathBuf {
+        if names.is_empty() {
+            return base_path.to_path_buf();
+        }
+        let variant: Vec<String> = names
+            .iter()
+            .map(|n| format!("{}={}", n, request_header(options, n).unwrap_or_default()))
+            .collect();
+        self.get_variant_file(file_name, &variant.join("\n"))
+    }
+
+    //Move a file that was just downloaded to the path of its variant when the response Vary header differs from the record.
+    //Returns the final path of the entry
+    pub(crate) fn settle_variant(&self, int_file_path: &Path, file_name: &str, options: &FetchOptions, metadata: &EntryMetadata) -> Result<PathBuf, Box<dyn Error>> {
+        let base_path = self.get_file(file_name);
+        let previous = load_vary_record(&base_path);
+        if previous == metadata.vary {
+            return Ok(int_file_path.to_path_buf());
+        }
+        //The variants keyed on the previous Vary set can no longer be selected
+        if !previous.is_empty() {
+            for path in self.variant_entries(file_name).into_iter().filter(|p| p != int_file_path) {
+                match fs::remove_file(&path) {
+                    Ok(()) => {
+                        EntryMetadata::remove(&path);
+                        self.stats.record_eviction();
+                    }
+                    Err(e) => log_error!("settle_variant","Unable to remove variant '{:?}' of '{}': {}", path, file_name, e),
+                }
+            }
+        }
+        save_vary_record(&base_path, &metadata.vary)?;
+        let target = self.variant_path(&base_path, file_name, options, &metadata.vary);
+        if target != int_file_path {
+            fs::rename(int_file_path, &target)?;
+            EntryMetadata::remove(int_file_path);
+        }
+        Ok(target)
+    }
+
+    //Every cached variant of a key (files whose metadata records the key) when the key has a Vary record
+    pub(crate) fn variant_entries(&self, file_name: &str) -> Vec<PathBuf> {
+        let base_path = self.get_file(file_name);
+        if !vary_record_path(&base_path).exists() {
+            return Vec::new();
+        }
+        match self.list_entries(&EntryFilter::new().with_key_prefix(file_name)) {
+            Ok(entries) => entries.into_iter().filter(|e| e.key == file_name).map(|e| e.path).collect(),
+            Err(e) => {
+                log_error!("variant_entries","Unable to list the variants of '{}': {}", file_name, e);
+                Vec::new()
+            }
+        }
+    }
+
+    //ASYNC Every cached variant of a key, the folder scan running off the calling task
+    #[cfg(feature = "async")]
+    pub(crate) async fn variant_entries_async(&self, file_name: &str) -> Vec<PathBuf> {
+        let (cache, file_name) = (self.shared(), file_name.to_owned());
+        nonblocking::unblock(move || Ok(cache.variant_entries(&file_name))).await.unwrap_or_default()
+    }
+
+    //Remove the Vary record of a key
+    pub(crate) fn remove_vary_record(&self, file_name: &str) {
+        let _ = fs::remove_file(vary_record_path(&self.get_file(file_name)));
+    }
+}
+
+//************** */
+//UNIT TEST    **/
+//************* */
+#[cfg(test)]
+mod vary_tests {
+    #[cfg(feature = "blocking")]
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{test_server::{TestResponse, TestServer}, test_support::TempCache};
+
+    #[cfg(feature = "blocking")]
+    fn language_server() -> TestServer {
+        TestServer::start(|req| {
+            let body = format!("lang={}", req.header("Accept-Language").unwrap_or("none"));
+            TestResponse::ok(body.as_bytes()).header("Vary", "Accept-Language, Accept-Encoding")
+        })
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_variants_do_not_overwrite_each_other() {
+        let server = language_server();
+        let local_cache = TempCache::new("vary");
+        let url = server.url("/vary");
+        let en = FetchOptions::new().with_header("Accept-Language", "en");
+        let fr = FetchOptions::new().with_header("Accept-Language", "fr");
+
+        let p_en = local_cache.get_local_file_path_with_options(&url, &url, &en).unwrap();
+        let p_fr = local_cache.get_local_file_path_with_options(&url, &url, &fr).unwrap();
+        let p_en2 = local_cache.get_local_file_path_with_options(&url, &url, &en).unwrap();
+
+        assert_ne!(p_en, p_fr);
+        assert_eq!(p_en, p_en2);
+        assert_eq!(fs::read(&p_en).unwrap(), b"lang=en");
+        assert_eq!(fs::read(&p_fr).unwrap(), b"lang=fr");
+        assert_eq!(server.request_count(), 2);
+        assert_eq!(local_cache.entry_info(&url).unwrap().unwrap().key, url);
+
+        local_cache.invalidate_cache(&url).unwrap();
+        assert!(!Path::new(&p_en).exists() && !Path::new(&p_fr).exists());
+        assert!(local_cache.entry_info(&url).unwrap().is_none());
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_vary_change_removes_old_variants() {
+        let vary = Arc::new(Mutex::new("Accept-Language"));
+        let th_vary = vary.clone();
+        let server = TestServer::start(move |req| {
+            let body = format!("lang={}", req.header("Accept-Language").unwrap_or("none"));
+            TestResponse::ok(body.as_bytes()).header("Vary", *th_vary.lock().unwrap())
+        });
+        let local_cache = TempCache::new("vary_change");
+        let url = server.url("/vary_change");
+        let en = FetchOptions::new().with_header("Accept-Language", "en");
+        let fr = FetchOptions::new().with_header("Accept-Language", "fr");
+        let p_en = local_cache.get_local_file_path_with_options(&url, &url, &en).unwrap();
+        let p_fr = local_cache.get_local_file_path_with_options(&url, &url, &fr).unwrap();
+        assert_eq!(local_cache.file_count(), 5);
+
+        *vary.lock().unwrap() = "Accept";
+        local_cache.refresh_cache_with_options(&url, &url, &en).unwrap();
+
+        assert!(!Path::new(&p_en).exists() && !Path::new(&p_fr).exists());
+        assert_eq!(local_cache.list_entries(&EntryFilter::new()).unwrap().len(), 1);
+        assert_eq!(local_cache.file_count(), 3);
+        assert_eq!(local_cache.stats().evictions, 2);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_vary_on_token_async() {
+        let server = TestServer::start(|req| {
+            TestResponse::ok(req.header("Authorization").unwrap_or("anonymous").as_bytes()).header("Vary", "Authorization")
+        });
+        let local_cache = TempCache::new("vary_token");
+        let url = server.url("/vary_token");
+
+        let a = local_cache.get_local_file_path_with_name_token_async(&url, "report", Some("user-a")).await.unwrap();
+        let b = local_cache.get_local_file_path_with_name_token_async(&url, "report", Some("user-b")).await.unwrap();
+
+        assert_eq!(fs::read(a).unwrap(), b"Bearer user-a");
+        assert_eq!(fs::read(b).unwrap(), b"Bearer user-b");
+    }
+
+    #[test]
+    fn test_vary_header_parsing() {
+        let mut headers = HeaderMap::new();
+        headers.append(VARY, "Accept-Language, accept".parse().unwrap());
+        headers.append(VARY, "*, Accept".parse().unwrap());
+        assert_eq!(vary_from_headers(&headers), vec!["accept".to_owned(), "accept-language".to_owned()]);
+    }
+}